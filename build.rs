@@ -30,7 +30,7 @@ fn main() {
 
     let git_status = run(vec!["git", "status", "--short"])
         .map(output_utf8)
-        .map(|v| if v == "" { "clean".to_string() } else { "dirty".to_string() })
+        .map(|v| if v.is_empty() { "clean".to_string() } else { "dirty".to_string() })
         .unwrap_or_else(|_| "unknown".to_string());
 
     let git_commit_hash = run(vec!["git", "rev-parse", "HEAD"])
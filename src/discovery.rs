@@ -0,0 +1,43 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// How nodes are found by node id, config `net.discovery` or --discovery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryMode {
+    /// The n0 DNS discovery, needs internet.
+    #[default]
+    N0,
+    /// mDNS on the local network only, for offline LANs.
+    Local,
+    /// Both n0 and the local network.
+    All,
+    /// Only the addresses in tickets.
+    None,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ping::{PING_ALPN, Ping, ping};
+    use anyhow::Result;
+    use iroh::{Endpoint, RelayMode, discovery::mdns::MdnsDiscovery, protocol::Router};
+
+    #[tokio::test]
+    async fn mdns_localhost() -> Result<()> {
+        let bind = async || {
+            Endpoint::builder()
+                .relay_mode(RelayMode::Disabled)
+                .add_discovery(|secret_key| MdnsDiscovery::new(secret_key.public()).ok())
+                .bind()
+                .await
+        };
+        let (a, b) = (bind().await?, bind().await?);
+        let router = Router::builder(a.clone()).accept(PING_ALPN, Ping).spawn();
+
+        // no addresses are added, b finds a by its node id only
+        let (_connect, _rtt) = ping(&b, a.node_id()).await?;
+
+        router.shutdown().await?;
+        Ok(())
+    }
+}
@@ -1,16 +1,33 @@
-use std::{path, process::Command, time::Instant};
+use std::{path, time::Instant};
 
+use crate::ping::ping;
+use crate::room::{Room, find_room, read_topic_tickets, write_topic_tickets};
+use crate::run::{RunOutput, run_command};
+use crate::schedule::{Schedule, Scheduled, parse_time};
 use crate::structs::{
-    COMMAND_HELP, COMMAND_ME, COMMAND_MEMBERS, COMMAND_QUIT, COMMAND_RECEIVE_FILE, COMMAND_RUN,
-    COMMAND_SEND_FILE, COMMAND_SHARE_FILE, DOC_HELP, EOF_BLOCK, MAX_FILESIZE, MemDB, Msg,
+    COMMAND_ALLOW, COMMAND_DELETE, COMMAND_EDIT, COMMAND_EPHEMERAL, COMMAND_HELP, COMMAND_HISTORY,
+    COMMAND_IGNORE, COMMAND_IGNORED, COMMAND_JOIN, COMMAND_LEAVE, COMMAND_MARK_VERIFIED,
+    COMMAND_ME, COMMAND_MEMBERS, COMMAND_MENTIONS, COMMAND_PEERS, COMMAND_PING, COMMAND_POLL,
+    COMMAND_POLL_RESULTS, COMMAND_QUIT, COMMAND_REACT, COMMAND_RECEIVE_FILE, COMMAND_REMIND,
+    COMMAND_REPLY, COMMAND_ROOMS, COMMAND_RUN, COMMAND_RUN_SHARE, COMMAND_SCHEDULE,
+    COMMAND_SCHEDULED, COMMAND_SEEN, COMMAND_SEND_FILE, COMMAND_SHARE_FILE, COMMAND_SWITCH,
+    COMMAND_THREAD, COMMAND_TICKET, COMMAND_TOPIC, COMMAND_UNIGNORE, COMMAND_UNSCHEDULE,
+    COMMAND_VERIFIED, COMMAND_VERIFY, COMMAND_VOTE, DOC_HELP, EOF_BLOCK, HistoryEntry,
+    MAX_EPHEMERAL_SECS, MAX_FILESIZE, MAX_MESSAGE_SIZE, MAX_POLL_OPTIONS, MemDB, Message,
+    MessageId, Msg, Poll, RunConfig, TYPING_INTERVAL, TopicLine, TopicTicket, expires_at,
+    is_valid_reaction, message_id, parse_mentions, quote_message,
+};
+use crate::transfer::{receive_file, share_bytes, share_file};
+use crate::utils::{
+    local_from_millis, parse_duration, qr_code, read_file_content, ring_bell, split_first_space,
 };
-use crate::transfer::{receive_file, share_file};
-use crate::utils::{read_file_content, split_first_space};
 
 use anyhow::Result;
-use iroh::{Endpoint, RelayMap, RelayMode, protocol::Router};
-use iroh_blobs::{net_protocol::Blobs, ticket::BlobTicket};
-use iroh_gossip::net::GossipSender;
+use bytes::Bytes;
+use chrono::Local;
+use iroh::{Endpoint, NodeId, endpoint::ConnectionType, protocol::Router};
+use iroh_blobs::{net_protocol::Blobs, rpc::client::blobs::MemClient, ticket::BlobTicket};
+use iroh_gossip::net::{Gossip, GossipSender};
 use tokio::io::{self, AsyncBufReadExt};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
@@ -20,8 +37,11 @@ use tracing::{error, info, warn}; // Level, instrument
 pub async fn input_loop(
     cancel_token: CancellationToken,
     mem_db: MemDB,
-    sender: GossipSender,
-    relay_map: RelayMap,
+    mut rooms: Vec<Room>,
+    gossip: Gossip,
+    endpoint: Endpoint,
+    blobs_endpoint: Endpoint,
+    run_config: RunConfig,
 ) -> Result<()> {
     // broadcast each line we type
     info!("==> Type a message and hit enter to broadcast...");
@@ -30,9 +50,6 @@ pub async fn input_loop(
     let eol = &['\r', '\n'][..];
     // println!("module_path = {}", module_path!());
 
-    let blobs_endpoint =
-        Endpoint::builder().relay_mode(RelayMode::Custom(relay_map)).discovery_n0().bind().await?;
-
     let blobs_node_id = blobs_endpoint.node_id(); // router.endpoint().node_id();
 
     // We initialize the Blobs protocol in-memory
@@ -65,6 +82,14 @@ pub async fn input_loop(
 
     let mut reader = io::BufReader::new(io::stdin()).lines();
     let mut buffer = String::new();
+    let mut current = 0; // the room we are talking in
+    let mut typing_at: Option<Instant> = None;
+
+    let mut schedule = Schedule::load(&name).await.unwrap_or_else(|e| {
+        warn!("load schedule: {e:?}");
+        Schedule::default()
+    });
+    let mut tick = time::interval(time::Duration::from_secs(1));
 
     // while let Some(line) = reader.next_line().await? {
     loop {
@@ -75,6 +100,10 @@ pub async fn input_loop(
                 break;
             }
             v = reader.next_line() => v?,
+            _ = tick.tick() => {
+                send_scheduled(&mut schedule, &rooms, &name, mem_db.notify.bell).await;
+                continue;
+            }
         };
 
         let line = match next_line {
@@ -85,14 +114,26 @@ pub async fn input_loop(
         if line.trim_end_matches(eol).ends_with(' ') {
             buffer.push_str(line.trim_end());
             buffer.push('\n');
+
+            // tell the room we are composing a message, throttled
+            if !buffer.starts_with("::") && typing_at.is_none_or(|v| v.elapsed() > TYPING_INTERVAL)
+            {
+                typing_at = Some(Instant::now());
+                let room = &rooms[current];
+                if let Err(e) = room.sender.broadcast(room.mem_db.sign_msg(Msg::Typing)).await {
+                    error!("Typing broadcast error: {e:?}");
+                }
+            }
             continue;
         }
 
         buffer.push_str(&line);
         let text = buffer.trim_end().to_string();
         buffer.clear();
+        typing_at = None;
 
         let (command, _) = split_first_space(&text, false);
+        let (mem_db, sender) = (rooms[current].mem_db.clone(), rooms[current].sender.clone());
 
         match command {
             COMMAND_QUIT => {
@@ -100,6 +141,93 @@ pub async fn input_loop(
             }
             COMMAND_ME => println!("node_id={node_id}, name={name:?}"),
             COMMAND_HELP => print!("{}", DOC_HELP),
+            COMMAND_PEERS => {
+                let members = mem_db.members.read().await.clone();
+                let neighbors = mem_db.neighbors.read().await.clone();
+
+                let mut node_ids: Vec<_> =
+                    neighbors.union(&members.keys().cloned().collect()).cloned().collect();
+                node_ids.sort();
+                if node_ids.is_empty() {
+                    println!("==> no peers in room: {}", rooms[current].label);
+                }
+
+                for peer_id in node_ids {
+                    let mut text =
+                        format!("- node_id: {peer_id}\n  name: {:?}", members.get(&peer_id));
+                    if neighbors.contains(&peer_id) {
+                        text.push_str("\n  tags: [neighbor]");
+                    }
+
+                    match endpoint.remote_info(peer_id) {
+                        Some(info) => {
+                            let (kind, addr) = match &info.conn_type {
+                                ConnectionType::Direct(v) => ("direct", Some(*v)),
+                                ConnectionType::Relay(_) => ("relay", None),
+                                ConnectionType::Mixed(v, _) => ("mixed", Some(*v)),
+                                ConnectionType::None => ("none", None),
+                            };
+                            let relay_url =
+                                info.relay_url.as_ref().map(|v| v.relay_url.to_string());
+
+                            text.push_str(&format!(
+                                "\n  conn_type: {kind}\n  addr: {:?}\n  relay_url: {relay_url:?}",
+                                addr.map(|v| v.to_string()),
+                            ));
+                            text.push_str(&format!(
+                                "\n  latency: {:?}\n  last_used: {:?}",
+                                info.latency, info.last_used,
+                            ));
+                        }
+                        None => text.push_str("\n  conn_type: unknown"),
+                    }
+                    println!("{text}");
+                }
+            }
+            COMMAND_PING => {
+                let targets: Vec<(NodeId, Option<String>)> =
+                    match shell_words::split(&text.replace("\n", " ")) {
+                        Ok(args) if args.len() == 2 && args[1] == "all" => {
+                            let members = mem_db.members.read().await;
+                            members.iter().map(|(k, v)| (*k, Some(v.clone()))).collect()
+                        }
+                        Ok(args) if args.len() == 2 => match mem_db.find_member(&args[1]).await {
+                            Ok((k, v)) => vec![(k, Some(v))],
+                            Err(e) => match args[1].parse::<NodeId>() {
+                                Ok(k) => vec![(k, None)],
+                                Err(_) => {
+                                    warn!("{command} {e}\n{EOF_BLOCK}");
+                                    continue;
+                                }
+                            },
+                        },
+                        _ => {
+                            warn!("{command} expected: <name|node_id|all>\n{EOF_BLOCK}");
+                            continue;
+                        }
+                    };
+
+                if targets.is_empty() {
+                    warn!("{command} no members in room: {}", rooms[current].label);
+                }
+
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move {
+                    let tasks = targets.into_iter().map(|(peer_id, peer_name)| {
+                        let endpoint = endpoint.clone();
+                        async move { (peer_id, peer_name, ping(&endpoint, peer_id).await) }
+                    });
+
+                    for (peer_id, peer_name, result) in futures::future::join_all(tasks).await {
+                        let text = match result {
+                            Ok((connect, rtt)) => format!("connect: {connect:?}\n  rtt: {rtt:?}"),
+                            Err(e) => format!("error: {e:?}"),
+                        };
+                        println!("- node_id: {peer_id}\n  name: {peer_name:?}\n  {text}");
+                    }
+                    println!("{EOF_BLOCK}");
+                });
+            }
             COMMAND_MEMBERS => {
                 let members = mem_db.members.read().await;
                 println!("- node_id: {node_id}\n  name: {name:?}\n  tags: [self]");
@@ -110,7 +238,717 @@ pub async fn input_loop(
                     println!("- node_id: {node_id}\n  name: {name:?}");
                 }
             }
-            COMMAND_RUN => {
+            COMMAND_ROOMS => {
+                for (i, room) in rooms.iter().enumerate() {
+                    let members = room.mem_db.members.read().await.len();
+                    let info = room.mem_db.room.read().await.clone();
+                    let mut text = format!("- room: {}\n  topic_id: {}", room.label, room.topic);
+
+                    if let Some(v) = &info.description {
+                        text.push_str(&format!("\n  description: {v:?}"));
+                    }
+                    if let Some(v) = &info.topic {
+                        text.push_str(&format!("\n  topic: {:?}", v.text));
+                    }
+                    text.push_str(&format!("\n  members: {members}"));
+                    if i == current {
+                        text.push_str("\n  tags: [current]");
+                    }
+                    println!("{text}");
+                }
+            }
+            COMMAND_TICKET => {
+                // a refreshed ticket of the current room by default, or any topic or blob ticket
+                let ticket = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 1 => {
+                        let ticket = match rooms[current].refresh_ticket(&endpoint).await {
+                            Ok(v) => v.to_string(),
+                            Err(e) => {
+                                error!("{command} refresh error: {e:?}\n{EOF_BLOCK}");
+                                continue;
+                            }
+                        };
+
+                        // rewrite the -w file, which has the tickets of all rooms opened with it
+                        if let Some(file) = rooms[current].ticket_file.clone() {
+                            let tickets: Vec<_> = rooms
+                                .iter()
+                                .filter(|v| v.ticket_file.as_ref() == Some(&file))
+                                .map(|v| &v.ticket)
+                                .collect();
+
+                            match write_topic_tickets(&tickets, &file).await {
+                                Ok(_) => info!("{command} ticket saved: {file}"),
+                                Err(e) => error!("{command} write ticket error: {file}, {e:?}"),
+                            }
+                        }
+                        ticket
+                    }
+                    Ok(args)
+                        if args.len() == 2
+                            && (args[1].parse::<TopicTicket>().is_ok()
+                                || args[1].parse::<BlobTicket>().is_ok()) =>
+                    {
+                        args[1].clone()
+                    }
+                    _ => {
+                        warn!("{command} expected: [topic_ticket|blob_ticket]\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match qr_code(&ticket) {
+                    Ok(v) => println!("{ticket}\n{v}"),
+                    Err(e) => error!("{command} error: {e:?}"),
+                }
+            }
+            COMMAND_TOPIC => {
+                let text = text[COMMAND_TOPIC.len()..].trim();
+
+                if text.is_empty() {
+                    // the room lock is released before taking the members lock
+                    let topic = mem_db.room.read().await.topic.clone();
+                    match topic {
+                        Some(v) => {
+                            let (node_id, name) = mem_db.node();
+                            let by = match v.by == node_id {
+                                true => Some(name),
+                                false => mem_db.members.read().await.get(&v.by).cloned(),
+                            };
+                            let at = local_from_millis(v.timestamp_ms)
+                                .map(|v| v.to_rfc3339())
+                                .unwrap_or_default();
+                            println!(
+                                "- topic: {:?}\n  by: {}\n  name: {:?}\n  at: {at}",
+                                v.text, v.by, by
+                            );
+                        }
+                        None => println!("==> no topic in room: {}", rooms[current].label),
+                    }
+                    continue;
+                }
+
+                let message = Message::new(Msg::Topic { text: text.to_string() });
+                let bts = mem_db.sign_message(&message);
+                let line = TopicLine {
+                    text: text.to_string(),
+                    by: mem_db.node().0,
+                    timestamp_ms: message.timestamp_ms,
+                    signed: bts.clone(),
+                };
+                mem_db.set_topic(line).await;
+
+                match sender.broadcast(bts).await {
+                    Ok(_) => info!("{command} ok: {text}"),
+                    Err(e) => error!("{command} broadcast error: {e:?}"),
+                }
+            }
+            COMMAND_REPLY => {
+                let (parent, text) =
+                    match split_first_space(text[COMMAND_REPLY.len()..].trim(), true) {
+                        (id, Some(text)) if !text.trim().is_empty() => {
+                            (id, text.trim().to_string())
+                        }
+                        _ => {
+                            warn!("{command} expected: <id> <text>\n{EOF_BLOCK}");
+                            continue;
+                        }
+                    };
+
+                let parent = match mem_db.find_message(parent).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match send_message(&mem_db, &sender, text, Some(parent.id), None).await {
+                    Ok(id) => info!(
+                        ">>> Message: you({name:?}), id={}, room={}\n{}",
+                        id.fmt_short(),
+                        rooms[current].label,
+                        quote_message(Some(&parent), &parent.id)
+                    ),
+                    Err(e) => {
+                        error!(">>> Message: you({name:?}), room={}, {e:?}", rooms[current].label)
+                    }
+                }
+            }
+            COMMAND_EPHEMERAL => {
+                let (ttl, text) = match split_first_space(text[command.len()..].trim(), true) {
+                    (ttl, Some(text)) if !text.trim().is_empty() => (ttl, text.trim().to_string()),
+                    _ => {
+                        warn!("{command} expected: <duration> <text>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let ttl_secs = match parse_duration(ttl).map(|v| v.as_secs()) {
+                    Ok(v) if (1..=MAX_EPHEMERAL_SECS).contains(&v) => v,
+                    Ok(_) => {
+                        warn!(
+                            "{command} duration must be 1s to {MAX_EPHEMERAL_SECS}s\n{EOF_BLOCK}"
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match send_message(&mem_db, &sender, text, None, Some(ttl_secs)).await {
+                    Ok(id) => info!(
+                        ">>> Message: you({name:?}), id={}, room={}, expires in {ttl_secs}s",
+                        id.fmt_short(),
+                        rooms[current].label
+                    ),
+                    Err(e) => {
+                        error!(">>> Message: you({name:?}), room={}, {e:?}", rooms[current].label)
+                    }
+                }
+            }
+            COMMAND_SCHEDULE | COMMAND_REMIND => {
+                let args = text[command.len()..].trim();
+                let (daily, args) = match args.strip_prefix("--daily ") {
+                    Some(v) => (true, v.trim_start()),
+                    None => (false, args),
+                };
+
+                let (at, text) = match split_first_space(args, true) {
+                    (at, Some(text)) if !text.trim().is_empty() => (at, text.trim().to_string()),
+                    _ => {
+                        warn!("{command} expected: [--daily] <time|duration> <text>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let at = match parse_time(at, Local::now()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let (topic, room) = match command {
+                    COMMAND_SCHEDULE => {
+                        (Some(rooms[current].topic.to_string()), rooms[current].label.clone())
+                    }
+                    _ => (None, String::new()),
+                };
+                let item = Scheduled { id: 0, at, text, topic, room, daily };
+                let id = schedule.add(item);
+                if let Err(e) = schedule.save(&name).await {
+                    error!("{command} save schedule: {e:?}");
+                }
+                info!("==> Scheduled: id={id}, at={}", at.to_rfc3339());
+            }
+            COMMAND_SCHEDULED => {
+                if schedule.items.is_empty() {
+                    println!("==> nothing scheduled");
+                }
+                for item in schedule.items.iter() {
+                    println!("{item}");
+                }
+            }
+            COMMAND_UNSCHEDULE => {
+                let id = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 && args[1].parse::<u32>().is_ok() => {
+                        args[1].parse::<u32>().unwrap()
+                    }
+                    _ => {
+                        warn!("{command} expected: <id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                if schedule.remove(id).is_none() {
+                    warn!("{command} not found: {id}\n{EOF_BLOCK}");
+                    continue;
+                }
+                if let Err(e) = schedule.save(&name).await {
+                    error!("{command} save schedule: {e:?}");
+                }
+                info!("==> Unscheduled: id={id}");
+            }
+            COMMAND_EDIT | COMMAND_DELETE => {
+                let (key, text) = match split_first_space(text[command.len()..].trim(), true) {
+                    (id, None) if command == COMMAND_DELETE && !id.is_empty() => (id, None),
+                    (id, Some(text)) if command == COMMAND_EDIT && !text.trim().is_empty() => {
+                        (id, Some(text.trim().to_string()))
+                    }
+                    _ if command == COMMAND_EDIT => {
+                        warn!("{command} expected: <id> <text>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                    _ => {
+                        warn!("{command} expected: <id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let id = match mem_db.find_message(key).await {
+                    Ok(v) => v.id,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let msg = match text.clone() {
+                    Some(text) => Msg::Edit { id, text },
+                    None => Msg::Delete { id },
+                };
+                let message = Message::new(msg);
+                let at = local_from_millis(message.timestamp_ms).unwrap_or_default();
+                // check it's ours before broadcasting, the others would drop it anyway
+                if let Err(e) = mem_db.edit_message(&node_id, &id, text, at).await {
+                    warn!("{command} {e}\n{EOF_BLOCK}");
+                    continue;
+                }
+
+                match sender.broadcast(mem_db.sign_message(&message)).await {
+                    Ok(_) => info!("{command} ok: {}", id.fmt_short()),
+                    Err(e) => error!("{command} broadcast error: {e:?}"),
+                }
+            }
+            COMMAND_THREAD => {
+                let id = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => args[1].clone(),
+                    _ => {
+                        warn!("{command} expected: <id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let id = match mem_db.find_message(&id).await {
+                    Ok(v) => v.id,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                for entry in mem_db.thread(&id).await {
+                    println!("{entry}");
+                }
+            }
+            COMMAND_HISTORY => {
+                let count = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 1 => 20,
+                    Ok(args) if args.len() == 2 && args[1].parse::<usize>().is_ok() => {
+                        args[1].parse::<usize>().unwrap()
+                    }
+                    _ => {
+                        warn!("{command} expected: [count]\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let history = mem_db.history.read().await;
+                if history.is_empty() {
+                    println!("==> no messages in room: {}", rooms[current].label);
+                }
+                for entry in history.iter().skip(history.len().saturating_sub(count)) {
+                    println!("{entry}");
+                }
+            }
+            COMMAND_MENTIONS => {
+                let count = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 1 => 20,
+                    Ok(args) if args.len() == 2 && args[1].parse::<usize>().is_ok() => {
+                        args[1].parse::<usize>().unwrap()
+                    }
+                    _ => {
+                        warn!("{command} expected: [count]\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let history = mem_db.history.read().await;
+                let mentions: Vec<_> = history.iter().filter(|v| v.highlighted).collect();
+                if mentions.is_empty() {
+                    println!("==> no mentions in room: {}", rooms[current].label);
+                }
+                for entry in mentions.iter().skip(mentions.len().saturating_sub(count)) {
+                    println!("{entry}");
+                }
+            }
+            COMMAND_POLL => {
+                let (question, options) = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if (4..=MAX_POLL_OPTIONS + 2).contains(&args.len()) => {
+                        (args[1].clone(), args[2..].to_vec())
+                    }
+                    _ => {
+                        warn!(
+                            "{command} expected: <question> <option>..., 2 to {MAX_POLL_OPTIONS} options\n{EOF_BLOCK}"
+                        );
+                        continue;
+                    }
+                };
+
+                let message = Message::new(Msg::Poll {
+                    question: question.clone(),
+                    options: options.clone(),
+                });
+                let bts = mem_db.sign_message(&message);
+                let id = message_id(&bts);
+                if let Err(e) = sender.broadcast(bts).await {
+                    error!("{command} broadcast error: {e:?}");
+                    continue;
+                }
+
+                let at = local_from_millis(message.timestamp_ms).unwrap_or_default();
+                let poll = Poll::new(id, node_id, name.clone(), at, question, options);
+                info!(
+                    "==> Poll: id={}\n{}\n{}",
+                    id.fmt_short(),
+                    poll.question,
+                    poll.display_options()
+                );
+                mem_db.add_poll(poll).await;
+            }
+            COMMAND_VOTE => {
+                let (key, n) = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 3 && args[2].parse::<usize>().is_ok() => {
+                        (args[1].clone(), args[2].parse::<usize>().unwrap())
+                    }
+                    _ => {
+                        warn!("{command} expected: <poll_id> <n>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let id = match mem_db.find_poll(&key).await {
+                    Ok(v) => v.id,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let message = Message::new(Msg::Vote { poll: id, n });
+                let at = local_from_millis(message.timestamp_ms).unwrap_or_default();
+                // check the option before broadcasting, the others would drop it anyway
+                let poll = match mem_db.vote(&id, n, node_id, name.clone(), at).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match sender.broadcast(mem_db.sign_message(&message)).await {
+                    Ok(_) => {
+                        info!("{command} ok: {}, {n}) {}", id.fmt_short(), poll.options[n - 1])
+                    }
+                    Err(e) => error!("{command} broadcast error: {e:?}"),
+                }
+            }
+            COMMAND_POLL_RESULTS => {
+                let key = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => args[1].clone(),
+                    _ => {
+                        warn!("{command} expected: <poll_id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match mem_db.find_poll(&key).await {
+                    Ok(v) => println!("{v}"),
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                }
+            }
+            COMMAND_SEEN => {
+                let key = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => args[1].clone(),
+                    _ => {
+                        warn!("{command} expected: <id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let entry = match mem_db.find_message(&key).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                // the current members and the nodes seen it but left
+                let mut nodes: Vec<(NodeId, String)> = mem_db
+                    .members
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|(k, _)| **k != entry.from && **k != node_id)
+                    .map(|(k, v)| (*k, v.clone()))
+                    .collect();
+                for (k, v) in entry.seen.iter() {
+                    if !nodes.iter().any(|(id, _)| id == k) {
+                        nodes.push((*k, v.0.clone()));
+                    }
+                }
+                nodes.sort_by(|a, b| a.1.cmp(&b.1));
+
+                println!("{entry}");
+                println!("==> seen by {} of {} member(s)", entry.seen.len(), nodes.len());
+                for (peer_id, peer_name) in nodes {
+                    let seen = entry.seen.get(&peer_id).map(|v| v.1.to_rfc3339());
+                    println!("- node_id: {peer_id}\n  name: {peer_name:?}\n  seen: {seen:?}");
+                }
+            }
+            COMMAND_REACT => {
+                let (key, emoji) = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 3 && is_valid_reaction(&args[2]) => {
+                        (args[1].clone(), args[2].clone())
+                    }
+                    _ => {
+                        warn!("{command} expected: <id> <emoji>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let id = match mem_db.find_message(&key).await {
+                    Ok(v) => v.id,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match mem_db.add_reaction(&id, emoji.clone(), node_id, name.clone()).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("{command} already reacted: {} {emoji}\n{EOF_BLOCK}", id.fmt_short());
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                }
+
+                let msg = Msg::Reaction { id, emoji: emoji.clone() };
+                match sender.broadcast(mem_db.sign_msg(msg)).await {
+                    Ok(_) => info!("{command} ok: {} {emoji}", id.fmt_short()),
+                    Err(e) => error!("{command} broadcast error: {e:?}"),
+                }
+            }
+            COMMAND_JOIN => {
+                let items = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() > 1 => args[1..].to_vec(),
+                    _ => {
+                        warn!("{command} expected: <ticket|file>...\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let tickets = match read_topic_tickets(&items).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} invalid ticket: {e:?}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                for ticket in tickets {
+                    // already in the room, just connect to more nodes
+                    if let Some(room) = rooms.iter().find(|v| v.topic == ticket.topic) {
+                        for node in ticket.nodes.iter() {
+                            if let Err(e) = endpoint.add_node_addr(node.clone()) {
+                                warn!("{command} can't add node: {}, {e:?}", node.node_id);
+                            }
+                        }
+
+                        let node_ids = ticket.nodes.iter().map(|v| v.node_id).collect();
+                        match room.sender.join_peers(node_ids).await {
+                            Ok(_) => info!("{command} joining more peers: {}", room.label),
+                            Err(e) => error!("{command} join peers error: {}, {e:?}", room.label),
+                        }
+                        continue;
+                    }
+
+                    match Room::join(&cancel_token, &gossip, &endpoint, &mem_db, ticket).await {
+                        Ok(room) => {
+                            let description = match &room.ticket.description {
+                                Some(v) => format!("\n    description: {v}"),
+                                None => "".to_string(),
+                            };
+                            info!(
+                                "{command} ok: {}{description}\n    ticket: {}",
+                                room.label, room.ticket
+                            );
+                            rooms.push(room);
+                            current = rooms.len() - 1;
+                        }
+                        Err(e) => error!("{command} error: {e:?}"),
+                    }
+                }
+            }
+            COMMAND_LEAVE | COMMAND_SWITCH => {
+                let i = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 1 && command == COMMAND_LEAVE => current,
+                    Ok(args) if args.len() == 2 => match find_room(&rooms, &args[1]) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("{command} {e}\n{EOF_BLOCK}");
+                            continue;
+                        }
+                    },
+                    _ => {
+                        warn!("{command} expected: <room>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                if command == COMMAND_SWITCH {
+                    current = i;
+                } else if rooms.len() == 1 {
+                    warn!("{command} can't leave the last room, use {COMMAND_QUIT} instead");
+                } else {
+                    let room = rooms.remove(i);
+                    if i < current || current >= rooms.len() {
+                        current -= 1;
+                    }
+
+                    let label = room.label.clone();
+                    match room.leave().await {
+                        Ok(_) => info!("{command} ok: {label}"),
+                        Err(e) => error!("{command} error: {e:?}"),
+                    }
+                }
+                println!("==> current room: {}", rooms[current].label);
+            }
+            COMMAND_ALLOW => {
+                let node_id: NodeId = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => match args[1].parse() {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("{command} invalid node_id: {e:?}\n{EOF_BLOCK}");
+                            continue;
+                        }
+                    },
+                    _ => {
+                        warn!("{command} expected: <node_id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match mem_db.allow(node_id) {
+                    Ok(true) => info!("{command} ok: {node_id}"),
+                    Ok(false) => info!("{command} already allowed: {node_id}"),
+                    Err(e) => warn!("{command} error: {e}"),
+                }
+            }
+            COMMAND_IGNORE => {
+                let key = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => args[1].clone(),
+                    _ => {
+                        warn!("{command} expected: <name|node_id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let (peer_id, peer_name) = match mem_db.find_member(&key).await {
+                    Ok(v) if v.0 == node_id => {
+                        warn!("{command} can't ignore yourself\n{EOF_BLOCK}");
+                        continue;
+                    }
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match mem_db.ignore(peer_id, peer_name.clone()).await {
+                    Ok(true) => info!("{command} ok: {peer_id}, name={peer_name:?}"),
+                    Ok(false) => info!("{command} already ignored: {peer_id}"),
+                    Err(e) => error!("{command} error: {e:?}"),
+                }
+            }
+            COMMAND_UNIGNORE => {
+                let key = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => args[1].clone(),
+                    _ => {
+                        warn!("{command} expected: <name|node_id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                match mem_db.unignore(&key).await {
+                    Ok((peer_id, peer_name)) => {
+                        info!("{command} ok: {peer_id}, name={peer_name:?}")
+                    }
+                    Err(e) => warn!("{command} {e}"),
+                }
+            }
+            COMMAND_IGNORED => {
+                let ignored = mem_db.ignored.read().await;
+
+                let mut ignored: Vec<_> = ignored.iter().collect();
+                ignored.sort_by(|a, b| a.1.cmp(b.1));
+                for (node_id, name) in ignored {
+                    println!("- node_id: {node_id}\n  name: {name:?}");
+                }
+            }
+            COMMAND_VERIFY | COMMAND_MARK_VERIFIED => {
+                let key = match shell_words::split(&text.replace("\n", " ")) {
+                    Ok(args) if args.len() == 2 => args[1].clone(),
+                    _ => {
+                        warn!("{command} expected: <name|node_id>\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                let (peer_id, peer_name) = match mem_db.find_member(&key).await {
+                    Ok(v) if v.0 == node_id => {
+                        warn!("{command} can't verify yourself\n{EOF_BLOCK}");
+                        continue;
+                    }
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("{command} {e}\n{EOF_BLOCK}");
+                        continue;
+                    }
+                };
+
+                if command == COMMAND_VERIFY {
+                    let verified = mem_db.verified.read().await.get(&peer_id).cloned();
+                    println!(
+                        "- node_id: {peer_id}\n  name: {peer_name:?}\n  verified: {verified:?}"
+                    );
+                    println!(
+                        "==> Compare the safety number with {peer_name:?} on another channel:"
+                    );
+                    println!("{}", mem_db.fingerprint(&peer_id));
+                    println!("==> Run \"{COMMAND_MARK_VERIFIED} {peer_id}\" if they match.");
+                } else if peer_name.is_empty() {
+                    warn!("{command} the member hasn't told us the name yet: {peer_id}");
+                } else {
+                    match mem_db.mark_verified(peer_id, peer_name.clone()).await {
+                        Ok(_) => info!("{command} ok: {peer_id}, name={peer_name:?}"),
+                        Err(e) => error!("{command} error: {e:?}"),
+                    }
+                }
+            }
+            COMMAND_VERIFIED => {
+                let verified = mem_db.verified.read().await;
+
+                let mut verified: Vec<_> = verified.iter().collect();
+                verified.sort_by(|a, b| a.1.cmp(b.1));
+                for (node_id, name) in verified {
+                    println!("- node_id: {node_id}\n  name: {name:?}");
+                }
+            }
+            COMMAND_RUN | COMMAND_RUN_SHARE => {
                 let args: Vec<String> = match shell_words::split(&text.replace("\n", " ")) {
                     Ok(v) if v.len() > 1 => v[1..].iter().map(|v| v.into()).collect(),
                     _ => {
@@ -119,14 +957,21 @@ pub async fn input_loop(
                     }
                 };
 
+                if !run_config.enabled {
+                    warn!("{command} is disabled by config\n{EOF_BLOCK}");
+                    continue;
+                }
+
                 // info!("{command} started: {args:?}");
+                let share = command == COMMAND_RUN_SHARE;
                 let command = command.to_string();
+                let run_config = run_config.clone();
+                let blobs_client = blobs_client.clone();
+                let sender = sender.clone();
+                let mem_db = mem_db.clone();
 
-                tokio::task::spawn_blocking(move || {
-                    let start = Instant::now();
-
-                    // Command.current_dir("/").env("PATH", "/bin")
-                    let output = match Command::new(&args[0]).args(&args[1..]).output() {
+                tokio::spawn(async move {
+                    let output = match run_command(&run_config, &args).await {
                         Ok(v) => v,
                         Err(e) => {
                             error!("{command} error: {args:?}, {e}\n{EOF_BLOCK}");
@@ -134,21 +979,37 @@ pub async fn input_loop(
                         }
                     };
 
-                    let elapsed = start.elapsed();
+                    let truncated = if output.truncated { " (truncated)" } else { "" };
 
-                    if output.status.success() {
-                        let stdout = String::from_utf8_lossy(&output.stdout);
+                    if output.success {
                         info!(
-                            "{} success: {:?}\n=== Elapsed: {:?}, stdout: \n{}",
-                            command, args, elapsed, stdout,
+                            "{} success: {:?}\n=== Elapsed: {:?}, stdout{}: \n{}",
+                            command, args, output.elapsed, truncated, output.stdout,
                         );
                     } else {
-                        let stderr = String::from_utf8_lossy(&output.stderr);
                         error!(
-                            "{} failed: {:?}\n=== Elapsed: {:?}, stderr: \n{}",
-                            command, args, elapsed, stderr,
+                            "{} failed: {:?}, {}\n=== Elapsed: {:?}, stderr{}: \n{}",
+                            command, args, output.status, output.elapsed, truncated, output.stderr,
                         );
                     }
+
+                    if share {
+                        let result =
+                            share_output(&blobs_client, blobs_node_id, &mem_db, args, output).await;
+
+                        let bts = match result {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("{command} share error: {e:?}\n{EOF_BLOCK}");
+                                return;
+                            }
+                        };
+
+                        match sender.broadcast(bts).await {
+                            Ok(_) => info!("{command} broadcast ok"),
+                            Err(e) => error!("{command} broadcast error: {e:?}"),
+                        }
+                    }
                     println!("{EOF_BLOCK}");
                 });
             }
@@ -261,23 +1122,121 @@ pub async fn input_loop(
                 });
             }
             v if v.starts_with("::") => error!("Unknown command: {v:?}"),
-            _ => {
-                let msg = Msg::Message { text: text };
-
-                match sender.broadcast(mem_db.sign_msg(msg)).await {
-                    Ok(_) => info!(">>> Message: you({name:?})"),
-                    Err(e) => error!(">>> Message: you({name:?}), {e:?}"),
+            _ => match send_message(&mem_db, &sender, text, None, None).await {
+                Ok(id) => info!(
+                    ">>> Message: you({name:?}), id={}, room={}",
+                    id.fmt_short(),
+                    rooms[current].label
+                ),
+                Err(e) => {
+                    error!(">>> Message: you({name:?}), room={}, {e:?}", rooms[current].label)
                 }
-            }
+            },
         }
 
         println!("{}", EOF_BLOCK);
     }
 
-    sender.broadcast(mem_db.sign_msg(Msg::Bye {})).await?;
-    time::sleep(time::Duration::from_millis(10)).await;
+    for room in rooms {
+        let label = room.label.clone();
+        if let Err(e) = room.leave().await {
+            error!("leave room: {label}, {e:?}");
+        }
+    }
 
     blobs_router.shutdown().await?;
     // info!("input_loop return");
     Ok(())
 }
+
+// Broadcast a chat message and keep it in the history, gossip doesn't deliver it back to us.
+async fn send_message(
+    mem_db: &MemDB,
+    sender: &GossipSender,
+    text: String,
+    reply_to: Option<MessageId>,
+    ttl_secs: Option<u64>,
+) -> Result<MessageId> {
+    let mentions = parse_mentions(&text, &*mem_db.members.read().await);
+    let msg = Msg::Message { text: text.clone(), reply_to, mentions: mentions.clone(), ttl_secs };
+    let message = Message::new(msg);
+    let bts = mem_db.sign_message(&message);
+    let id = message_id(&bts);
+    sender.broadcast(bts).await?;
+
+    let (from, name) = mem_db.node();
+    let at = local_from_millis(message.timestamp_ms)?;
+    let mut entry = HistoryEntry::new(id, from, name, at, text, reply_to);
+    entry.mentions = mentions;
+    entry.expires_at = ttl_secs.and_then(|v| expires_at(at, v));
+    mem_db.add_history(entry).await;
+    Ok(id)
+}
+
+// Send the due messages of the schedule and show the due reminders.
+async fn send_scheduled(schedule: &mut Schedule, rooms: &[Room], profile: &str, bell: bool) {
+    let (due, late) =
+        schedule.take_due(Local::now(), |t| rooms.iter().any(|r| r.topic.to_string() == t));
+    if due.is_empty() && late.is_empty() {
+        return;
+    }
+
+    for item in due {
+        let Some(room) = rooms.iter().find(|r| item.topic == Some(r.topic.to_string())) else {
+            info!("=== Reminder: {}", item.text);
+            if bell {
+                ring_bell();
+            }
+            continue;
+        };
+
+        let name = room.mem_db.node().1;
+        match send_message(&room.mem_db, &room.sender, item.text, None, None).await {
+            Ok(id) => info!(
+                ">>> Message: you({name:?}), id={}, scheduled={}, room={}",
+                id.fmt_short(),
+                item.id,
+                room.label
+            ),
+            Err(e) => error!(">>> Message: you({name:?}), scheduled={}, {e:?}", item.id),
+        }
+    }
+
+    for item in late {
+        warn!("skipped scheduled: id={}, at={}, too late", item.id, item.at.to_rfc3339());
+    }
+
+    if let Err(e) = schedule.save(profile).await {
+        error!("save schedule: {e:?}");
+    }
+}
+
+// Sign the command output, move stdout and stderr into a blob if it's too large for gossip.
+async fn share_output(
+    blobs_client: &MemClient,
+    blobs_node_id: NodeId,
+    mem_db: &MemDB,
+    args: Vec<String>,
+    output: RunOutput,
+) -> Result<Bytes> {
+    let msg = |stdout: String, stderr: String, ticket: Option<BlobTicket>| Msg::CommandOutput {
+        args: args.clone(),
+        success: output.success,
+        status: output.status.clone(),
+        elapsed_ms: output.elapsed.as_millis() as u64,
+        stdout,
+        stderr,
+        truncated: output.truncated,
+        ticket,
+    };
+
+    let bts = mem_db.sign_msg(msg(output.stdout.clone(), output.stderr.clone(), None));
+    if bts.len() <= MAX_MESSAGE_SIZE - 256 {
+        return Ok(bts);
+    }
+
+    let content = format!("--- stdout:\n{}\n--- stderr:\n{}", output.stdout, output.stderr);
+    let (_, ticket) = share_bytes(blobs_client, blobs_node_id, content.into_bytes()).await?;
+
+    Ok(mem_db.sign_msg(msg(String::new(), String::new(), Some(ticket))))
+}
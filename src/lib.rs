@@ -1,6 +1,11 @@
 mod input_loop;
 mod subscribe_loop;
 
+pub mod discovery;
+pub mod ping;
+pub mod room;
+pub mod run;
+pub mod schedule;
 pub mod structs;
 pub mod transfer;
 pub mod utils;
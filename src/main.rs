@@ -1,18 +1,28 @@
-use std::{fmt::Debug, path, str::FromStr};
-
-use iroh_chat_cli::structs::{MemDB, Msg, TopicTicket};
+use std::{fmt::Debug, net::SocketAddr, str::FromStr, time::Duration};
+
+use iroh_chat_cli::discovery::DiscoveryMode;
+use iroh_chat_cli::input_loop;
+use iroh_chat_cli::ping::{PING_ALPN, Ping};
+use iroh_chat_cli::room::{
+    Room, inspect_topic_ticket, merge_topic_tickets, parse_topic_tickets, read_topic_tickets,
+    write_topic_tickets,
+};
+use iroh_chat_cli::structs::{
+    MAX_MESSAGE_SIZE, MemDB, NetConfig, NotifyConfig, RunConfig, TopicTicket,
+};
 use iroh_chat_cli::utils::{self, build_info};
-use iroh_chat_cli::{input_loop, subscribe_loop};
 
 use anyhow::{Result, anyhow};
-use clap::{ArgAction, Args, Parser};
-use futures::{FutureExt, pin_mut};
-use iroh::{Endpoint, NodeAddr, RelayMap, RelayMode, RelayUrl, SecretKey, protocol::Router};
+use clap::{ArgAction, Parser};
+use iroh::{
+    Endpoint, NodeId, RelayMap, RelayMode, RelayUrl, SecretKey,
+    discovery::mdns::MdnsDiscovery,
+    protocol::{AccessLimit, Router},
+};
 use tokio_util::sync::CancellationToken;
 /* RelayUrlParseError, RelayNode */
 use iroh_gossip::{net::Gossip, proto::TopicId};
-use rand::prelude::*;
-use tokio::{fs, io::AsyncWriteExt, signal};
+use tokio::{signal, time};
 use tracing::{error, info, warn}; // Level, instrument
 use tracing_subscriber::EnvFilter;
 
@@ -31,14 +41,22 @@ use tracing_subscriber::EnvFilter;
     after_help = build_info(),
 )]
 struct Command {
-    /*
-    /// Set the bind port for our socket. By default, a random port will be used.
-    #[clap(short, long, default_value = "0")]
-    bind_port: u16,
-    */
-    /// Set your nickname.
+    /// Bind address of the gossip endpoint, "0.0.0.0:4433", "[::]:4433" or just a port.
+    /// By default, a random port will be used.
+    #[arg(long)]
+    bind: Option<String>,
+
+    /// Bind address of the blobs endpoint for file sharing, like --bind.
+    #[arg(long)]
+    blobs_bind: Option<String>,
+
+    /// How to find nodes by node id, "local" works on LANs without internet. [default: n0]
+    #[arg(long, value_enum)]
+    discovery: Option<DiscoveryMode>,
+
+    /// Set your nickname, required to open or join rooms.
     #[clap(short, long)]
-    name: String,
+    name: Option<String>,
 
     #[arg(short = 'r', long, action=ArgAction::Append)]
     relay_url: Vec<String>,
@@ -46,6 +64,10 @@ struct Command {
     #[clap(short, long)] // default_value = "configs/local.yaml"
     config: Option<String>,
 
+    /// Private room: only accept node ids in config room.allowlist or added with ::allow.
+    #[arg(long)]
+    allowlist: bool,
+
     /// run in debug mode
     #[arg(long)]
     verbose: bool,
@@ -61,38 +83,71 @@ enum Subcommand {
         /// Optional file path to save the ticket; by default, the ticket is printed.
         #[arg(short = 'w', long)]
         write_ticket: Option<String>,
+
+        /// A human-readable room name, carried in the ticket.
+        #[arg(long)]
+        room_name: Option<String>,
+
+        /// A short room description, carried in the ticket.
+        #[arg(long)]
+        description: Option<String>,
+
+        /// Also print the ticket as a QR code.
+        #[arg(long)]
+        qr: bool,
     },
 
-    /// Join a chat room from a ticket.
+    /// Join chat rooms from tickets, one room for each topic.
     Join {
-        /// The ticket can be provided as a base32 string or a file path.
-        ticket: String,
+        /// The tickets can be provided as base32 strings or file paths.
+        #[arg(required = true, num_args = 1..)]
+        tickets: Vec<String>,
 
         /// Optional file path to save the ticket; by default, the ticket is printed.
         #[arg(short = 'w', long)]
         write_ticket: Option<String>,
+
+        /// Also print the tickets as QR codes.
+        #[arg(long)]
+        qr: bool,
+    },
+
+    /// Report our NAT and relay reachability, with the same relay, bind and discovery options.
+    Doctor {
+        /// Seconds to wait for the network report.
+        #[arg(long, default_value = "10")]
+        timeout_secs: u64,
     },
-    // Join(JoinCommand),
+
+    /// Inspect or merge tickets without joining.
+    #[clap(subcommand)]
+    Ticket(TicketCommand),
 }
 
-#[derive(Debug, Args)]
-struct JoinCommand {
-    ticket: String,
+#[derive(Parser, Debug)]
+enum TicketCommand {
+    /// Decode tickets and print the topic, node ids, relay urls and direct addresses.
+    Inspect {
+        /// The tickets can be provided as base32 strings or file paths.
+        #[arg(required = true, num_args = 1..)]
+        tickets: Vec<String>,
+    },
 
-    // --ticket t1 --ticket t2 --ticket t3
-    #[arg(short='t', long ="ticket", action=ArgAction::Append)]
-    tickets_v1: Vec<String>,
+    /// Combine the node lists of tickets, one ticket for each topic.
+    Merge {
+        /// The tickets can be provided as base32 strings or file paths.
+        #[arg(required = true, num_args = 1..)]
+        tickets: Vec<String>,
 
-    /// t1 t2 t3
-    #[arg(required = true, num_args = 1..)]
-    tickets_v2: Vec<String>,
+        /// Optional file path to save the tickets; by default, the tickets are printed.
+        #[arg(short = 'w', long)]
+        write_ticket: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Command::parse();
-    let name = args.name.clone();
-
     let filter = if args.verbose {
         EnvFilter::new("debug")
     } else {
@@ -101,24 +156,32 @@ async fn main() -> Result<()> {
     };
     utils::log2stdout(filter);
 
-    let (topic, ticket_nodes, write_ticket) = match &args.subcommand {
-        Subcommand::Open { write_ticket } => {
+    if let Subcommand::Ticket(v) = &args.subcommand {
+        return ticket_command(v).await;
+    }
+
+    let (tickets, write_ticket, qr) = match &args.subcommand {
+        Subcommand::Open { write_ticket, room_name, description, qr } => {
             let topic = TopicId::from_bytes(rand::random());
             println!("==> Opening chat room for topic {topic}");
-            (topic, vec![], write_ticket)
-        }
-        Subcommand::Join { ticket: ticket_str, write_ticket } => {
-            // let TopicTicket { topic, nodes } = if ticket.contains(".") {
-            let topic_ticket = if ticket_str.contains(".") {
-                let ticket_str = fs::read_to_string(&ticket_str).await?;
-                TopicTicket::from_str(&ticket_str.trim())?
-            } else {
-                TopicTicket::from_str(&ticket_str)?
+            let ticket = TopicTicket {
+                topic,
+                nodes: vec![],
+                name: room_name.clone(),
+                description: description.clone(),
             };
+            (vec![ticket], write_ticket, *qr)
+        }
+        Subcommand::Join { tickets, write_ticket, qr } => {
+            let tickets = read_topic_tickets(tickets).await?;
 
-            println!("==> Joining chat room for ticket: {topic_ticket:?}");
-            (topic_ticket.topic, topic_ticket.nodes, write_ticket)
+            for v in tickets.iter() {
+                println!("==> Joining chat room for ticket:\n{}", inspect_topic_ticket(v));
+            }
+            (tickets, write_ticket, *qr)
         }
+        Subcommand::Doctor { .. } => (vec![], &None, false),
+        Subcommand::Ticket(_) => unreachable!(),
     };
 
     //let relay_url = endpoint.home_relay().initialized().await.unwrap();
@@ -138,143 +201,169 @@ async fn main() -> Result<()> {
         RelayMap::from_iter(urls)
     };
 
-    let secret_key: SecretKey = match args.config {
-        Some(v) => {
-            let yaml = utils::load_yaml(&v)?;
+    let yaml = match &args.config {
+        Some(v) => Some(utils::load_yaml(v)?),
+        None => None,
+    };
 
-            let val = utils::config_get(&yaml, "iroh.secret_key")
+    let secret_key: SecretKey = match &yaml {
+        Some(yaml) => {
+            let val = utils::config_get(yaml, "iroh.secret_key")
                 .ok_or(anyhow!("can't get iroh.secret_key from config"))?;
 
             let val = serde_yaml::to_string(val)?;
-            SecretKey::from_str(&val.trim())?
+            SecretKey::from_str(val.trim())?
         }
         None => utils::iroh_secret_key(),
     };
 
-    let endpoint = Endpoint::builder()
-        .relay_mode(RelayMode::Custom(relay_map.clone()))
-        .secret_key(secret_key.clone())
-        .discovery_n0()
-        .bind()
-        .await?;
+    let allowlist: Option<Vec<NodeId>> = if args.allowlist {
+        let items: Vec<String> =
+            match yaml.as_ref().and_then(|v| utils::config_get(v, "room.allowlist")) {
+                Some(v) => serde_yaml::from_value(v.clone())
+                    .map_err(|e| anyhow!("invalid room.allowlist in config: {e}"))?,
+                None => vec![],
+            };
+
+        let node_ids = items.iter().map(|v| NodeId::from_str(v)).collect::<Result<_, _>>()?;
+        Some(node_ids)
+    } else {
+        None
+    };
+
+    let run_config: RunConfig = match yaml.as_ref().and_then(|v| utils::config_get(v, "run")) {
+        Some(v) => {
+            serde_yaml::from_value(v.clone()).map_err(|e| anyhow!("invalid run in config: {e}"))?
+        }
+        None => RunConfig::default(),
+    };
+
+    let notify_config: NotifyConfig =
+        match yaml.as_ref().and_then(|v| utils::config_get(v, "notify")) {
+            Some(v) => serde_yaml::from_value(v.clone())
+                .map_err(|e| anyhow!("invalid notify in config: {e}"))?,
+            None => NotifyConfig::default(),
+        };
 
-    let mem_db = MemDB::new(secret_key, endpoint.node_id(), name.clone());
+    let net_config: NetConfig = match yaml.as_ref().and_then(|v| utils::config_get(v, "net")) {
+        Some(v) => {
+            serde_yaml::from_value(v.clone()).map_err(|e| anyhow!("invalid net in config: {e}"))?
+        }
+        None => NetConfig::default(),
+    };
+    let bind = args.bind.or(net_config.bind).map(|v| utils::parse_bind_addr(&v)).transpose()?;
+    let blobs_bind = args
+        .blobs_bind
+        .or(net_config.blobs_bind)
+        .map(|v| utils::parse_bind_addr(&v))
+        .transpose()?;
+
+    let discovery = args.discovery.unwrap_or(net_config.discovery);
+
+    let endpoint = bind_endpoint(&relay_map, discovery, Some(secret_key.clone()), bind).await?;
+    // the blobs endpoint has its own node id
+    let blobs_endpoint = bind_endpoint(&relay_map, discovery, None, blobs_bind).await?;
+
+    if let Subcommand::Doctor { timeout_secs } = args.subcommand {
+        return doctor(&endpoint, Duration::from_secs(timeout_secs)).await;
+    }
+
+    let name = args.name.clone().ok_or(anyhow!("--name is required to open or join rooms"))?;
+    let mut mem_db =
+        MemDB::new(secret_key, endpoint.node_id(), name.clone()).with_notify(notify_config);
+    if let Some(v) = allowlist {
+        println!("==> Allowlist mode: {} node(s) allowed", v.len());
+        mem_db = mem_db.with_allowlist(v);
+    }
+    mem_db.load_nodes().await?;
     // Get our address information, includes our `NodeId`, our `RelayUrl`, and any direct addresses.
     let node_addr = endpoint.node_addr().await?;
 
     // Build and instance of the gossip protocol and add a clone of the endpoint we have built.
     // The gossip protocol will use the endpoint to make connections.
-    let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
+    let gossip =
+        Gossip::builder().max_message_size(MAX_MESSAGE_SIZE).spawn(endpoint.clone()).await?;
 
     // The Router is how we manage protocols on top of the iroh endpoint. It handles all incoming
     // messages and routes them to the correct protocol.
-    let router =
-        Router::builder(endpoint.clone()).accept(iroh_gossip::ALPN, gossip.clone()).spawn();
+    // Connections from nodes out of the allowlist are refused before reaching the gossip protocol.
+    let router = {
+        let (db1, db2) = (mem_db.clone(), mem_db.clone());
+        let gossip_handler = AccessLimit::new(gossip.clone(), move |v| db1.is_allowed(&v));
+        let ping_handler = AccessLimit::new(Ping, move |v| db2.is_allowed(&v));
+
+        Router::builder(endpoint.clone())
+            .accept(iroh_gossip::ALPN, gossip_handler)
+            .accept(PING_ALPN, ping_handler)
+            .spawn()
+    };
     // println!("iroh_gossip::ALPN: {}", String::from_utf8(iroh_gossip::ALPN.to_vec()).unwrap());
     // iroh_gossip::ALPN: /iroh-gossip/0
 
-    // in our main file, after we create a topic `id`:
-    // print a ticket that includes our own node id and endpoint addresses
-    let mut all_nodes: Vec<NodeAddr> =
-        ticket_nodes.choose_multiple(&mut rand::rng(), 3).map(|x| (*x).clone()).collect();
-
-    all_nodes.push(node_addr.clone());
-
-    let ticket = TopicTicket { topic, nodes: all_nodes };
-    // dbg!(&ticket);
-
     // println!("--> node: {node_addr:?}\n    ticket: {ticket}");
     println!("--> node: {:?}", mem_db.node());
     println!("    relay_url: {:?}", node_addr.relay_url());
     println!("    direct_addresses: {:?}", node_addr.direct_addresses().collect::<Vec<_>>());
 
+    let cancel_token = CancellationToken::new();
+
+    let mut rooms = Vec::with_capacity(tickets.len());
+    for ticket in tickets {
+        let room = Room::join(&cancel_token, &gossip, &endpoint, &mem_db, ticket).await?;
+        println!("    room: {}", room.label);
+        if let Some(v) = &room.ticket.description {
+            println!("    description: {v}");
+        }
+        rooms.push(room);
+    }
+
+    // in our main file, after we create a topic `id`:
+    // print a ticket that includes our own node id and endpoint addresses
     if let Some(v) = write_ticket {
-        write_topic_ticket(&ticket, &v).await?;
+        write_topic_tickets(&rooms.iter().map(|v| &v.ticket).collect::<Vec<_>>(), v).await?;
+        rooms.iter_mut().for_each(|room| room.ticket_file = Some(v.clone()));
         println!("    ticket: {v}");
     } else {
-        println!("    ticket: {ticket}");
+        for room in rooms.iter() {
+            println!("    ticket: {}", room.ticket);
+        }
     }
 
-    // join the gossip topic by connecting to known nodes, if any
-    let node_ids = ticket_nodes.iter().map(|p| p.node_id).collect();
-
-    if ticket_nodes.is_empty() {
-        info!("waiting for nodes to join us...");
-    } else {
-        // add the peer addrs from the ticket to our endpoint's addressbook,
-        // so that they can be dialed
-        for node in ticket_nodes.into_iter() {
-            // println!("--> trying to connect to node: {:?}...", node);
-            if let Err(e) = endpoint.add_node_addr(node.clone()) {
-                warn!("can't connect to node: {}, {e:?}", node.node_id);
-            } else {
-                info!("connected to node: {}", node.node_id);
+    if qr {
+        for room in rooms.iter() {
+            match utils::qr_code(&room.ticket.to_string()) {
+                Ok(v) => println!("==> room: {}\n{v}", room.label),
+                Err(e) => error!("qr code of room {}: {e:?}", room.label),
             }
         }
     }
 
-    // dbg!(&node_ids);
-    let (sender, receiver) = gossip.subscribe_and_join(topic, node_ids).await?.split();
-    info!("connected!");
-
-    let about_me = Msg::AboutMe { name: name.clone() };
-    sender.broadcast(mem_db.sign_msg(about_me).into()).await?;
-
-    /*
-    tokio::spawn(subscribe_loop(mem_db.clone(), sender.clone(), receiver));
-
-    if let Err(e) = input_loop(mem_db.clone(), sender.clone(), relay_map).await {
-        error!("input_loop: {e:?}");
-    }
-    */
-
-    let cancel_token = CancellationToken::new();
-
-    let task1 = tokio::task::spawn(subscribe_loop(
+    let mut task = tokio::task::spawn(input_loop(
         cancel_token.clone(),
         mem_db.clone(),
-        sender.clone(),
-        receiver,
+        rooms,
+        gossip.clone(),
+        endpoint.clone(),
+        blobs_endpoint,
+        run_config,
     ));
 
-    let task2 = tokio::task::spawn(input_loop(
-        cancel_token.clone(),
-        mem_db.clone(),
-        sender.clone(),
-        relay_map,
-    ));
-
-    let (fuse1, fuse2) = (task1.fuse(), task2.fuse());
-    pin_mut!(fuse1, fuse2);
-
-    let (result1, result2) = tokio::select! {
-        v = &mut fuse1 => {
-            warn!("subscribe_loop exited.");
-            cancel_token.cancel();
-            (v, fuse2.await)
-        }
-        v = &mut fuse2 => {
+    let result = tokio::select! {
+        v = &mut task => {
             warn!("input_loop exited.");
             cancel_token.cancel();
-            (fuse1.await, v)
+            v
         }
         _ = signal::ctrl_c() => {
-            println!("");
+            println!();
             error!("<-- received Ctrl+C.");
             warn!("--> cancel token");
             cancel_token.cancel();
-            (fuse1.await, fuse2.await)
+            task.await
         }
     };
 
-    // let (result1, result2) = tokio::join!(task1, task2);
-    match result1 {
-        Ok(Ok(_)) => info!("subscribe_loop returns ok."),
-        Ok(Err(e)) => error!("subscribe_loop returns error: {e:?}"),
-        Err(e) => error!("subscribe_loop joins error: {e:?}"),
-    }
-
-    match result2 {
+    match result {
         Ok(Ok(_)) => info!("input_loop returns ok."),
         Ok(Err(e)) => error!("input_loop return error: {e:?}"),
         Err(e) => error!("input_loop joins error: {e:?}"),
@@ -287,19 +376,124 @@ async fn main() -> Result<()> {
     std::process::exit(0);
 }
 
-pub async fn write_topic_ticket(ticket: &TopicTicket, filename: &str) -> Result<()> {
-    // fs::create_dir_all(dir).await?;
-    // let filepath = dir.join(format!("{}.topic.ticket", filename));
+/// Build an endpoint with the relays, discovery and an optional bind address.
+async fn bind_endpoint(
+    relay_map: &RelayMap,
+    discovery: DiscoveryMode,
+    secret_key: Option<SecretKey>,
+    bind: Option<SocketAddr>,
+) -> Result<Endpoint> {
+    let mut builder = Endpoint::builder().relay_mode(RelayMode::Custom(relay_map.clone()));
+
+    if matches!(discovery, DiscoveryMode::N0 | DiscoveryMode::All) {
+        builder = builder.discovery_n0();
+    }
+    if matches!(discovery, DiscoveryMode::Local | DiscoveryMode::All) {
+        builder =
+            builder.add_discovery(|secret_key| match MdnsDiscovery::new(secret_key.public()) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("local discovery error: {e:?}");
+                    None
+                }
+            });
+    }
 
-    let filepath = path::Path::new(filename);
-    if let Some(p) = filepath.parent() {
-        fs::create_dir_all(p).await?;
+    if let Some(v) = secret_key {
+        builder = builder.secret_key(v);
     }
 
-    let mut file = fs::File::create(&filepath).await?;
-    // file.write_all(&ticket.to_bytes()).await?;
-    file.write_all(&ticket.base32_bytes()).await?;
-    file.write_all(b"\n").await?;
+    builder = match bind {
+        Some(SocketAddr::V4(v)) => builder.bind_addr_v4(v),
+        Some(SocketAddr::V6(v)) => builder.bind_addr_v6(v),
+        None => builder,
+    };
+
+    let endpoint = builder.bind().await.map_err(|e| anyhow!("bind endpoint {bind:?}: {e}"))?;
+
+    // iroh falls back to a random port when the address is in use
+    if let Some(v) = bind {
+        let (v4, v6) = endpoint.bound_sockets();
+        let bound: Vec<_> = std::iter::once(v4).chain(v6).collect();
+        if v.port() != 0 && !bound.iter().any(|b| b.port() == v.port()) {
+            endpoint.close().await;
+            return Err(anyhow!("bind endpoint {v}: the port is in use, bound to {bound:?}"));
+        }
+    }
+
+    Ok(endpoint)
+}
+
+async fn doctor(endpoint: &Endpoint, timeout: Duration) -> Result<()> {
+    println!("==> Waiting for the network report, up to {}s...", timeout.as_secs());
+    let report = time::timeout(timeout, endpoint.net_report().initialized()).await;
+    let home_relay = time::timeout(Duration::from_secs(1), endpoint.home_relay().initialized())
+        .await
+        .ok()
+        .and_then(|v| v.ok());
+
+    let (v4, v6) = endpoint.bound_sockets();
+    let node_addr = endpoint.node_addr().await?;
+    println!("- node_id: {}", endpoint.node_id());
+    println!("  bound_sockets: {:?}", std::iter::once(v4).chain(v6).collect::<Vec<_>>());
+    println!("  direct_addresses: {:?}", node_addr.direct_addresses().collect::<Vec<_>>());
+    println!("  home_relay: {:?}", home_relay.map(|v| v.to_string()));
+
+    let report = match report {
+        Ok(Ok(v)) => v,
+        _ => {
+            warn!("no network report, no relay or stun server is reachable");
+            println!("  verdict: only direct addresses in tickets or on the local network work");
+            return Ok(());
+        }
+    };
+
+    let opt = |v: Option<bool>| v.map(|v| v.to_string()).unwrap_or("unknown".to_string());
+    println!("  udp: {}", report.udp);
+    println!("  ipv4: {}, global_v4: {:?}", report.ipv4, report.global_v4);
+    println!("  ipv6: {}, global_v6: {:?}", report.ipv6, report.global_v6);
+    println!("  mapping_varies_by_dest_ip: {}", opt(report.mapping_varies_by_dest_ip));
+    println!("  hair_pinning: {}", opt(report.hair_pinning));
+    println!("  captive_portal: {}", opt(report.captive_portal));
+    if let Some(v) = &report.portmap_probe {
+        println!("  portmap: upnp={}, pcp={}, nat_pmp={}", v.upnp, v.pcp, v.nat_pmp);
+    }
+    println!("  preferred_relay: {:?}", report.preferred_relay.as_ref().map(|v| v.to_string()));
+    println!("  relay_latency:");
+    for (url, latency) in report.relay_latency.iter() {
+        println!("  - {url}: {latency:?}");
+    }
+
+    let verdict = match (report.udp, report.mapping_varies_by_dest_ip) {
+        (false, _) => "udp is blocked, all traffic goes through the relay",
+        (true, Some(true)) => "hard NAT, direct connections are unlikely, the relay is used",
+        (true, Some(false)) => "easy NAT, direct connections should work via hole punching",
+        (true, None) => "udp works, the NAT type is unknown",
+    };
+    println!("  verdict: {verdict}");
+
+    Ok(())
+}
+
+async fn ticket_command(command: &TicketCommand) -> Result<()> {
+    match command {
+        TicketCommand::Inspect { tickets } => {
+            for v in parse_topic_tickets(tickets).await? {
+                println!("{}", inspect_topic_ticket(&v));
+            }
+        }
+        TicketCommand::Merge { tickets, write_ticket } => {
+            let tickets = merge_topic_tickets(parse_topic_tickets(tickets).await?);
+
+            match write_ticket {
+                Some(v) => {
+                    write_topic_tickets(&tickets.iter().collect::<Vec<_>>(), v).await?;
+                    println!("==> {} ticket(s) saved: {v}", tickets.len());
+                }
+                None => tickets.iter().for_each(|v| println!("{v}")),
+            }
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use iroh::{Endpoint, NodeId, endpoint::Connection, protocol::ProtocolHandler};
+use n0_future::boxed::BoxFuture;
+use tokio::time;
+
+/// A request/response protocol next to gossip, the payload is echoed back.
+pub const PING_ALPN: &[u8] = b"/iroh-chat-cli/ping/0";
+pub const PING_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_SIZE: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Ping;
+
+impl ProtocolHandler for Ping {
+    fn accept(&self, connection: Connection) -> BoxFuture<Result<()>> {
+        Box::pin(async move {
+            // a connection can carry several pings, until the other side closes it
+            while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                let payload = recv.read_to_end(PING_SIZE).await?;
+                send.write_all(&payload).await?;
+                send.finish()?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Send a ping to the node, returns the time to connect and the round-trip time.
+pub async fn ping(endpoint: &Endpoint, node_id: NodeId) -> Result<(Duration, Duration)> {
+    let result = time::timeout(PING_TIMEOUT, async {
+        let start = Instant::now();
+        let connection = endpoint.connect(node_id, PING_ALPN).await?;
+        let connect = start.elapsed();
+
+        let start = Instant::now();
+        let payload: [u8; PING_SIZE] = rand::random();
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(&payload).await?;
+        send.finish()?;
+
+        let echo = recv.read_to_end(PING_SIZE).await?;
+        let rtt = start.elapsed();
+        connection.close(0u32.into(), b"bye");
+
+        if echo != payload {
+            return Err(anyhow!("invalid pong"));
+        }
+        Ok((connect, rtt))
+    })
+    .await;
+
+    result.map_err(|_| anyhow!("timeout after {PING_TIMEOUT:?}"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::{RelayMode, protocol::Router};
+
+    #[tokio::test]
+    async fn ping_localhost() -> Result<()> {
+        let bind = async || Endpoint::builder().relay_mode(RelayMode::Disabled).bind().await;
+        let (a, b) = (bind().await?, bind().await?);
+        let router = Router::builder(a.clone()).accept(PING_ALPN, Ping).spawn();
+
+        b.add_node_addr(a.node_addr().await?)?;
+        let (_connect, rtt) = ping(&b, a.node_id()).await?;
+        assert!(rtt < PING_TIMEOUT);
+
+        router.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::HashMap, path};
+
+use crate::structs::{MAX_TICKET_NODES, MemDB, Msg, RoomInfo, TopicTicket};
+use crate::subscribe_loop;
+
+use anyhow::{Result, anyhow};
+use iroh::{Endpoint, NodeAddr, NodeId};
+use iroh_gossip::{
+    net::{Gossip, GossipSender},
+    proto::TopicId,
+};
+use rand::prelude::*;
+use tokio::{fs, io::AsyncWriteExt, task::JoinHandle, time};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, error, info, info_span, warn};
+
+/// A joined gossip topic with its own member list.
+pub struct Room {
+    pub topic: TopicId,
+    pub label: String,
+    pub sender: GossipSender,
+    pub mem_db: MemDB,
+    /// The ticket for others to join, includes our own node.
+    pub ticket: TopicTicket,
+    /// The file the ticket was written to, rewritten when the ticket is refreshed.
+    pub ticket_file: Option<String>,
+    cancel_token: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl Room {
+    /// Subscribe to the topic of the ticket and spawn a subscribe_loop for it.
+    pub async fn join(
+        cancel_token: &CancellationToken,
+        gossip: &Gossip,
+        endpoint: &Endpoint,
+        mem_db: &MemDB,
+        ticket: TopicTicket,
+    ) -> Result<Self> {
+        let TopicTicket { topic, nodes, name, description } = ticket;
+        let label = name.clone().unwrap_or_else(|| topic.to_string()[..8].to_string());
+
+        // add the peer addrs from the ticket to our endpoint's addressbook,
+        // so that they can be dialed
+        for node in nodes.iter() {
+            if let Err(e) = endpoint.add_node_addr(node.clone()) {
+                warn!("can't add node: {}, {e:?}", node.node_id);
+            }
+        }
+
+        if nodes.is_empty() {
+            info!("waiting for nodes to join us: {label}");
+        }
+
+        // print a ticket that includes our own node id and endpoint addresses
+        let mut all_nodes: Vec<NodeAddr> =
+            nodes.choose_multiple(&mut rand::rng(), 3).map(|x| (*x).clone()).collect();
+        all_nodes.push(endpoint.node_addr().await?);
+
+        // join the gossip topic by connecting to known nodes, if any
+        let node_ids: Vec<NodeId> = nodes.iter().map(|p| p.node_id).collect();
+        let (sender, receiver) = gossip.subscribe(topic, node_ids.clone())?.split();
+
+        let info = RoomInfo { name: name.clone(), description: description.clone(), topic: None };
+        let mem_db = mem_db.for_room(info);
+        let cancel_token = cancel_token.child_token();
+
+        let task = tokio::spawn(
+            {
+                let (cancel_token, mem_db, sender) =
+                    (cancel_token.clone(), mem_db.clone(), sender.clone());
+
+                async move {
+                    let result =
+                        subscribe_loop(cancel_token, mem_db, sender, receiver, node_ids).await;
+                    if let Err(e) = result {
+                        error!("subscribe_loop returns error: {e:?}");
+                    }
+                }
+            }
+            .instrument(info_span!("room", name = %label)),
+        );
+
+        Ok(Self {
+            topic,
+            label,
+            sender,
+            mem_db,
+            ticket: TopicTicket { topic, nodes: all_nodes, name, description },
+            ticket_file: None,
+            cancel_token,
+            task,
+        })
+    }
+
+    /// Rebuild the ticket from the current neighbors and members, with their known addresses.
+    pub async fn refresh_ticket(&mut self, endpoint: &Endpoint) -> Result<&TopicTicket> {
+        let mut node_ids: Vec<NodeId> =
+            self.mem_db.neighbors.read().await.iter().cloned().collect();
+        for node_id in self.mem_db.members.read().await.keys() {
+            if !node_ids.contains(node_id) {
+                node_ids.push(*node_id);
+            }
+        }
+
+        let mut nodes: Vec<NodeAddr> = node_ids
+            .into_iter()
+            .filter_map(|v| endpoint.remote_info(v))
+            .map(NodeAddr::from)
+            .filter(|v| v.relay_url.is_some() || !v.direct_addresses.is_empty())
+            .take(MAX_TICKET_NODES)
+            .collect();
+        nodes.push(endpoint.node_addr().await?);
+
+        self.ticket.nodes = nodes;
+        Ok(&self.ticket)
+    }
+
+    /// Say bye to the room and stop its subscribe_loop.
+    pub async fn leave(self) -> Result<()> {
+        let result = self.sender.broadcast(self.mem_db.sign_msg(Msg::Bye {})).await;
+        time::sleep(time::Duration::from_millis(10)).await;
+
+        self.cancel_token.cancel();
+        self.task.await?;
+        result.map_err(Into::into)
+    }
+}
+
+/// Find a room by label or topic.
+pub fn find_room(rooms: &[Room], key: &str) -> Result<usize> {
+    rooms
+        .iter()
+        .position(|v| v.label == key || v.topic.to_string() == key)
+        .ok_or(anyhow!("unknown room: {key:?}"))
+}
+
+/// The ticket can be provided as a base32 string or a file path, a file can contain several
+/// tickets, one per line. Tickets of the same topic are merged.
+pub async fn read_topic_tickets(items: &[String]) -> Result<Vec<TopicTicket>> {
+    Ok(merge_topic_tickets(parse_topic_tickets(items).await?))
+}
+
+/// Like read_topic_tickets, but every ticket is kept as it is.
+pub async fn parse_topic_tickets(items: &[String]) -> Result<Vec<TopicTicket>> {
+    let mut tickets: Vec<TopicTicket> = Vec::new();
+
+    for item in items {
+        let lines = if item.contains(".") {
+            fs::read_to_string(&item).await.map_err(|e| anyhow!("read {item}: {e}"))?
+        } else {
+            item.clone()
+        };
+
+        for line in lines.lines().map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            tickets.push(line.parse().map_err(|e| anyhow!("invalid ticket in {item}: {e}"))?);
+        }
+    }
+
+    if tickets.is_empty() {
+        return Err(anyhow!("no ticket found"));
+    }
+
+    Ok(tickets)
+}
+
+/// Merge the node lists of tickets with the same topic, the first name and description win.
+pub fn merge_topic_tickets(items: Vec<TopicTicket>) -> Vec<TopicTicket> {
+    let mut tickets: Vec<TopicTicket> = Vec::new();
+    let mut index: HashMap<TopicId, usize> = HashMap::new();
+
+    for ticket in items {
+        let Some(&i) = index.get(&ticket.topic) else {
+            index.insert(ticket.topic, tickets.len());
+            tickets.push(ticket);
+            continue;
+        };
+
+        let target = &mut tickets[i];
+        for node in ticket.nodes {
+            match target.nodes.iter_mut().find(|v| v.node_id == node.node_id) {
+                Some(v) => {
+                    v.direct_addresses.extend(node.direct_addresses);
+                    v.relay_url = v.relay_url.take().or(node.relay_url);
+                }
+                None => target.nodes.push(node),
+            }
+        }
+        target.name = target.name.take().or(ticket.name);
+        target.description = target.description.take().or(ticket.description);
+    }
+
+    tickets
+}
+
+/// Describe the ticket for humans.
+pub fn inspect_topic_ticket(ticket: &TopicTicket) -> String {
+    let mut text = format!("- topic_id: {}", ticket.topic);
+
+    if let Some(v) = &ticket.name {
+        text.push_str(&format!("\n  name: {v:?}"));
+    }
+    if let Some(v) = &ticket.description {
+        text.push_str(&format!("\n  description: {v:?}"));
+    }
+
+    text.push_str("\n  nodes:");
+    if ticket.nodes.is_empty() {
+        text.push_str(" []");
+    }
+    for node in ticket.nodes.iter() {
+        let relay_url = node.relay_url.as_ref().map(|v| v.to_string()).unwrap_or_default();
+        let addrs: Vec<_> = node.direct_addresses.iter().map(|v| v.to_string()).collect();
+
+        text.push_str(&format!(
+            "\n  - node_id: {}\n    relay_url: {:?}\n    direct_addresses: [{}]",
+            node.node_id,
+            relay_url,
+            addrs.join(", "),
+        ));
+    }
+
+    text
+}
+
+/// Write tickets to the file, one per line.
+pub async fn write_topic_tickets(tickets: &[&TopicTicket], filename: &str) -> Result<()> {
+    // fs::create_dir_all(dir).await?;
+    // let filepath = dir.join(format!("{}.topic.ticket", filename));
+
+    let filepath = path::Path::new(filename);
+    if let Some(p) = filepath.parent() {
+        fs::create_dir_all(p).await?;
+    }
+
+    let mut file = fs::File::create(&filepath).await?;
+    for ticket in tickets {
+        // file.write_all(&ticket.to_bytes()).await?;
+        file.write_all(&ticket.base32_bytes()).await?;
+        file.write_all(b"\n").await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::iroh_secret_key;
+
+    #[test]
+    fn merge_tickets_by_topic() {
+        let (a, b) = (iroh_secret_key().public(), iroh_secret_key().public());
+        let topic = TopicId::from_bytes(rand::random());
+        let ticket = |nodes: Vec<NodeAddr>, name: Option<&str>| TopicTicket {
+            topic,
+            nodes,
+            name: name.map(String::from),
+            description: None,
+        };
+
+        let addr: std::net::SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        let tickets = merge_topic_tickets(vec![
+            ticket(vec![NodeAddr::new(a)], None),
+            ticket(
+                vec![NodeAddr::new(a).with_direct_addresses([addr]), NodeAddr::new(b)],
+                Some("dev"),
+            ),
+            TopicTicket { topic: TopicId::from_bytes(rand::random()), ..ticket(vec![], None) },
+        ]);
+
+        assert_eq!(tickets.len(), 2);
+        assert_eq!(tickets[0].name.as_deref(), Some("dev"));
+        assert_eq!(tickets[0].nodes.len(), 2);
+        assert!(tickets[0].nodes[0].direct_addresses.contains(&addr));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{path, process::Stdio, time::Duration};
+
+use crate::structs::RunConfig;
+
+use anyhow::{Result, anyhow};
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+use tokio::{process::Command, time};
+
+#[derive(Debug)]
+pub struct RunOutput {
+    pub success: bool,
+    pub status: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub elapsed: Duration,
+    pub truncated: bool,
+}
+
+pub async fn run_command(config: &RunConfig, args: &[String]) -> Result<RunOutput> {
+    if !config.enabled {
+        return Err(anyhow!("::run is disabled by config"));
+    }
+
+    let program = args.first().ok_or(anyhow!("no program"))?;
+    if !config.allowlist.is_empty() && !config.allowlist.contains(program) {
+        return Err(anyhow!("program is not in the allowlist: {program}"));
+    }
+
+    let mut command = Command::new(program);
+    command
+        .args(&args[1..])
+        .env_clear()
+        .envs(std::env::vars().filter(|(k, _)| config.env.contains(k)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    if let Some(v) = &config.workdir {
+        command.current_dir(path::Path::new(v));
+    }
+
+    let start = time::Instant::now();
+    let mut child = command.spawn()?;
+    let (stdout, stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+
+    let result = time::timeout(Duration::from_secs(config.timeout_secs), async {
+        tokio::try_join!(
+            read_capped(stdout, config.max_output),
+            read_capped(stderr, config.max_output),
+            child.wait(),
+        )
+    })
+    .await;
+
+    let ((stdout, truncated1), (stderr, truncated2), status) = match result {
+        Ok(v) => v?,
+        Err(_) => {
+            child.kill().await?;
+            return Err(anyhow!("killed after timeout {}s", config.timeout_secs));
+        }
+    };
+
+    Ok(RunOutput {
+        success: status.success(),
+        status: status.to_string(),
+        stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stderr: String::from_utf8_lossy(&stderr).to_string(),
+        elapsed: start.elapsed(),
+        truncated: truncated1 || truncated2,
+    })
+}
+
+// keep reading after the limit, so the child isn't blocked on a full pipe
+async fn read_capped<R: AsyncRead + Unpin>(
+    mut reader: R,
+    max: usize,
+) -> io::Result<(Vec<u8>, bool)> {
+    let (mut buf, mut chunk, mut truncated) = (Vec::new(), [0u8; 8192], false);
+
+    loop {
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+
+        let room = max.saturating_sub(buf.len());
+        if n > room {
+            truncated = true;
+        }
+        buf.extend(&chunk[..n.min(room)]);
+    }
+
+    Ok((buf, truncated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn run_timeout() {
+        let config = RunConfig { timeout_secs: 1, ..Default::default() };
+
+        let err = run_command(&config, &args(&["sleep", "5"])).await.unwrap_err();
+        println!("--> {err}");
+        assert!(err.to_string().contains("timeout"));
+    }
+
+    #[tokio::test]
+    async fn run_limits() {
+        let config =
+            RunConfig { allowlist: vec!["echo".into()], max_output: 4, ..Default::default() };
+
+        let output = run_command(&config, &args(&["echo", "hello"])).await.unwrap();
+        assert!(output.success && output.truncated);
+        assert_eq!(output.stdout, "hell");
+
+        assert!(run_command(&config, &args(&["ls"])).await.is_err());
+
+        let config = RunConfig { enabled: false, ..Default::default() };
+        assert!(run_command(&config, &args(&["echo"])).await.is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::structs::SCHEDULED_FILE;
+use crate::utils::{parse_duration, profile_dir};
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+// a due item is dropped when it's later than this, e.g. we were offline at the time
+const GRACE_SECS: i64 = 5 * 60;
+
+/// Scheduled messages and local reminders of a user, persisted in profile_dir()/scheduled.yaml.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    next_id: u32,
+    pub items: Vec<Scheduled>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scheduled {
+    #[serde(default)]
+    pub id: u32,
+    pub at: DateTime<Local>,
+    pub text: String,
+    // the topic id of the room to send the message to, None for a local reminder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub room: String,
+    // repeat at the same time every day
+    #[serde(default)]
+    pub daily: bool,
+}
+
+impl Schedule {
+    pub async fn load(name: &str) -> Result<Self> {
+        let filepath = profile_dir(name)?.join(SCHEDULED_FILE);
+        if !filepath.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&filepath).await?;
+        serde_yaml::from_str(&content).map_err(|e| anyhow!("invalid {}: {e}", filepath.display()))
+    }
+
+    pub async fn save(&self, name: &str) -> Result<()> {
+        let dir = profile_dir(name)?;
+        fs::create_dir_all(&dir).await?;
+        fs::write(dir.join(SCHEDULED_FILE), serde_yaml::to_string(self)?).await?;
+        Ok(())
+    }
+
+    /// Returns the id assigned to the item.
+    pub fn add(&mut self, mut item: Scheduled) -> u32 {
+        self.next_id += 1;
+        item.id = self.next_id;
+        self.items.push(item);
+        self.items.sort_by_key(|v| v.at);
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Scheduled> {
+        let index = self.items.iter().position(|v| v.id == id)?;
+        Some(self.items.remove(index))
+    }
+
+    /// Take the due items and the ones too late to be sent, a message waits for its room to be
+    /// joined until then. The daily items are moved to the next day instead.
+    pub fn take_due(
+        &mut self,
+        now: DateTime<Local>,
+        joined: impl Fn(&str) -> bool,
+    ) -> (Vec<Scheduled>, Vec<Scheduled>) {
+        let (mut due, mut late, mut done) = (vec![], vec![], vec![]);
+        if self.items.iter().all(|v| v.at > now) {
+            return (due, late);
+        }
+
+        for item in self.items.iter_mut().filter(|v| v.at <= now) {
+            if (now - item.at).num_seconds() > GRACE_SECS {
+                late.push(item.clone());
+            } else if item.topic.as_deref().is_none_or(&joined) {
+                due.push(item.clone());
+            } else {
+                continue;
+            }
+
+            if !item.daily {
+                done.push(item.id);
+            }
+            while item.daily && item.at <= now {
+                item.at += chrono::Duration::days(1);
+            }
+        }
+
+        self.items.retain(|v| !done.contains(&v.id));
+        self.items.sort_by_key(|v| v.at);
+        (due, late)
+    }
+}
+
+impl std::fmt::Display for Scheduled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match &self.topic {
+            Some(_) => format!("message\n  room: {:?}", self.room),
+            None => "reminder".to_string(),
+        };
+        write!(
+            f,
+            "- id: {}\n  at: {}\n  kind: {kind}\n  daily: {}\n  text: {:?}",
+            self.id,
+            self.at.to_rfc3339(),
+            self.daily,
+            self.text
+        )
+    }
+}
+
+/// Parse a time like "5m"(a duration), "09:55"(the next one), "2026-10-20T09:55" or RFC 3339.
+pub fn parse_time(text: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    if let Ok(v) = parse_duration(text) {
+        return chrono::Duration::from_std(v)
+            .ok()
+            .and_then(|v| now.checked_add_signed(v))
+            .ok_or_else(|| anyhow!("duration too long: {text:?}"));
+    }
+
+    if let Ok(v) = NaiveTime::parse_from_str(text, "%H:%M") {
+        let mut at = local_time(now.date_naive().and_time(v))?;
+        if at <= now {
+            at = local_time((now + chrono::Duration::days(1)).date_naive().and_time(v))?;
+        }
+        return Ok(at);
+    }
+
+    if let Ok(v) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M") {
+        return local_time(v);
+    }
+
+    DateTime::parse_from_rfc3339(text)
+        .map(|v| v.with_timezone(&Local))
+        .map_err(|_| anyhow!("invalid time: {text:?}, expected a duration, HH:MM or a datetime"))
+}
+
+fn local_time(v: NaiveDateTime) -> Result<DateTime<Local>> {
+    Local.from_local_datetime(&v).earliest().ok_or_else(|| anyhow!("invalid local time: {v}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        let now = Local::now();
+        assert_eq!(parse_time("5m", now).unwrap(), now + chrono::Duration::minutes(5));
+
+        let at = parse_time("09:55", now).unwrap();
+        assert!(at > now && at <= now + chrono::Duration::days(1));
+        assert_eq!(at.format("%H:%M").to_string(), "09:55");
+
+        assert!(parse_time("2026-10-20T09:55", now).is_ok());
+        assert!(parse_time("2026-10-20T09:55:00+08:00", now).is_ok());
+        assert!(parse_time("tomorrow", now).is_err());
+        assert!(parse_time("99999999999d", now).is_err());
+    }
+
+    #[test]
+    fn take_due_items() {
+        let now = Local::now();
+        let item = |secs: i64, topic: Option<&str>, daily| Scheduled {
+            id: 0,
+            at: now + chrono::Duration::seconds(secs),
+            text: "standup in 5 minutes".into(),
+            topic: topic.map(Into::into),
+            room: "dev".into(),
+            daily,
+        };
+
+        let mut schedule = Schedule::default();
+        let reminder = schedule.add(item(-1, None, false));
+        let daily = schedule.add(item(-2, Some("a"), true));
+        let waiting = schedule.add(item(-3, Some("b"), false));
+        let late = schedule.add(item(-GRACE_SECS - 10, Some("a"), false));
+        let later = schedule.add(item(60, Some("a"), false));
+
+        let (due, skipped) = schedule.take_due(now, |v| v == "a");
+        let ids = |items: &[Scheduled]| items.iter().map(|v| v.id).collect::<Vec<_>>();
+        assert_eq!(ids(&due), vec![daily, reminder]);
+        assert_eq!(ids(&skipped), vec![late]);
+
+        // the daily one is moved to tomorrow, the one for room b waits
+        assert_eq!(ids(&schedule.items), vec![waiting, later, daily]);
+        assert!(schedule.items[2].at > now + chrono::Duration::hours(23));
+        assert!(schedule.remove(waiting).is_some() && schedule.remove(waiting).is_none());
+    }
+}
@@ -1,6 +1,13 @@
-use std::{collections::HashMap, fmt, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
 
-use crate::utils::local_from_millis;
+use crate::discovery::DiscoveryMode;
+use crate::utils::{local_from_millis, profile_dir};
 
 use anyhow::{Result, anyhow};
 use chrono::{DateTime, Local, Utc};
@@ -11,22 +18,75 @@ use iroh::{NodeAddr, NodeId, SecretKey};
 use iroh_blobs::ticket::BlobTicket;
 use iroh_gossip::proto::TopicId;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::{fs, sync::RwLock};
 
 pub const COMMAND_QUIT: &str = "::quit";
 pub const COMMAND_ME: &str = "::me";
 pub const COMMAND_HELP: &str = "::help";
 pub const COMMAND_MEMBERS: &str = "::members";
+pub const COMMAND_PEERS: &str = "::peers";
+pub const COMMAND_PING: &str = "::ping";
 pub const COMMAND_RUN: &str = "::run";
+pub const COMMAND_RUN_SHARE: &str = "::run!";
+pub const COMMAND_ALLOW: &str = "::allow";
+pub const COMMAND_ROOMS: &str = "::rooms";
+pub const COMMAND_JOIN: &str = "::join";
+pub const COMMAND_LEAVE: &str = "::leave";
+pub const COMMAND_SWITCH: &str = "::switch";
+pub const COMMAND_TOPIC: &str = "::topic";
+pub const COMMAND_TICKET: &str = "::ticket";
+pub const COMMAND_REPLY: &str = "::reply";
+pub const COMMAND_THREAD: &str = "::thread";
+pub const COMMAND_EDIT: &str = "::edit";
+pub const COMMAND_DELETE: &str = "::delete";
+pub const COMMAND_REACT: &str = "::react";
+pub const COMMAND_HISTORY: &str = "::history";
+pub const COMMAND_MENTIONS: &str = "::mentions";
+pub const COMMAND_SEEN: &str = "::seen";
+pub const COMMAND_EPHEMERAL: &str = "::ephemeral";
+pub const COMMAND_SCHEDULE: &str = "::schedule";
+pub const COMMAND_REMIND: &str = "::remind";
+pub const COMMAND_SCHEDULED: &str = "::scheduled";
+pub const COMMAND_UNSCHEDULE: &str = "::unschedule";
+pub const COMMAND_POLL: &str = "::poll";
+pub const COMMAND_VOTE: &str = "::vote";
+pub const COMMAND_POLL_RESULTS: &str = "::poll_results";
+pub const COMMAND_IGNORE: &str = "::ignore";
+pub const COMMAND_UNIGNORE: &str = "::unignore";
+pub const COMMAND_IGNORED: &str = "::ignored";
+pub const COMMAND_VERIFY: &str = "::verify";
+pub const COMMAND_MARK_VERIFIED: &str = "::mark_verified";
+pub const COMMAND_VERIFIED: &str = "::verified";
 
 pub const COMMAND_SEND_FILE: &str = "::send_file";
 pub const COMMAND_SHARE_FILE: &str = "::share_file";
 pub const COMMAND_RECEIVE_FILE: &str = "::receive_file";
 
 pub const MAX_FILESIZE: u64 = 8 * 1024 * 1024;
+// the default of iroh-gossip, larger messages are shared as blobs
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+// peers in a refreshed ticket besides ourselves, to keep it short
+pub const MAX_TICKET_NODES: usize = 5;
+// messages kept per room for replies and threads
+pub const MAX_HISTORY: usize = 1000;
+// polls kept per room for votes and results
+pub const MAX_POLLS: usize = 100;
+pub const MAX_POLL_OPTIONS: usize = 10;
+// the longest lifetime of an ephemeral message
+pub const MAX_EPHEMERAL_SECS: u64 = 7 * 24 * 60 * 60;
+// at most one typing notification in the interval, it's shown again after twice the interval
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+// messages stamped further in the future are rejected, old ones are fine (relayed topics)
+pub const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
 pub const EOF_BLOCK: &str = "----------------------------------------------------------------";
 
 pub const DOC_HELP: &str = include_str!("help.md");
+pub const IGNORED_FILE: &str = "ignored.yaml";
+pub const VERIFIED_FILE: &str = "verified.yaml";
+pub const SCHEDULED_FILE: &str = "scheduled.yaml";
+
+/// The hash of the signed payload, the same on every node.
+pub type MessageId = iroh_blobs::Hash;
 
 // add the message code to the bottom
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,20 +115,350 @@ impl Message {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Msg {
-    AboutMe { name: String },
+    AboutMe {
+        name: String,
+        // the signed Msg::Topic of the room known by the sender, for late joiners
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signed_topic: Option<Vec<u8>>,
+    },
     Bye,
-    Message { text: String },
-    SendFile { filename: String, content: Vec<u8> },
-    ShareFile { filename: String, size: u64, ticket: BlobTicket },
+    // ask the others to send a fresh AboutMe, after messages are lost
+    Resync,
+    Message {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<MessageId>,
+        // the members mentioned with @name
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        mentions: Vec<NodeId>,
+        // an ephemeral message expires at timestamp_ms + ttl_secs, purged from the history
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl_secs: Option<u64>,
+    },
+    SendFile {
+        filename: String,
+        content: Vec<u8>,
+    },
+    ShareFile {
+        filename: String,
+        size: u64,
+        ticket: BlobTicket,
+    },
+    // the output is in the blob of ticket when it's too large for a gossip message
+    CommandOutput {
+        args: Vec<String>,
+        success: bool,
+        status: String,
+        elapsed_ms: u64,
+        stdout: String,
+        stderr: String,
+        truncated: bool,
+        ticket: Option<BlobTicket>,
+    },
+    Topic {
+        text: String,
+    },
+    // only the author of the message can edit or delete it
+    Edit {
+        id: MessageId,
+        text: String,
+    },
+    Delete {
+        id: MessageId,
+    },
+    Reaction {
+        id: MessageId,
+        emoji: String,
+    },
+    // composing a multi-line message, not stored
+    Typing,
+    // the messages displayed by the sender, batched
+    Receipt {
+        ids: Vec<MessageId>,
+    },
+    // the id of the poll is the id of this message
+    Poll {
+        question: String,
+        options: Vec<String>,
+    },
+    // the option number from 1, the latest vote of a node wins
+    Vote {
+        poll: MessageId,
+        n: usize,
+    },
+}
+
+/// The topic line of a room, the latest one wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicLine {
+    pub text: String,
+    pub by: NodeId,
+    pub timestamp_ms: i64,
+    // the signed Msg::Topic, relayed in AboutMe
+    pub signed: Bytes,
+}
+
+impl TopicLine {
+    /// Verify a relayed Msg::Topic, the author and time are taken from the signed message.
+    pub fn from_signed(signed: Bytes) -> Result<Self> {
+        let (by, _, message) = parse_raw_message(&signed)?;
+        match message.msg {
+            Msg::Topic { text } => {
+                Ok(Self { text, by, timestamp_ms: message.timestamp_ms, signed })
+            }
+            _ => Err(anyhow!("not a topic message")),
+        }
+    }
+}
+
+/// A chat message in the room history.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub id: MessageId,
+    pub from: NodeId,
+    pub name: String,
+    pub at: DateTime<Local>,
+    pub text: String,
+    pub reply_to: Option<MessageId>,
+    // the time of the latest edit
+    pub edited: Option<DateTime<Local>>,
+    pub deleted: bool,
+    // emoji => the names of the nodes reacted with it
+    pub reactions: BTreeMap<String, BTreeMap<NodeId, String>>,
+    pub mentions: Vec<NodeId>,
+    // it mentions us or matches a keyword of config notify.keywords
+    pub highlighted: bool,
+    // node => name and the time of its receipt
+    pub seen: BTreeMap<NodeId, (String, DateTime<Local>)>,
+    pub expires_at: Option<DateTime<Local>>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        id: MessageId,
+        from: NodeId,
+        name: String,
+        at: DateTime<Local>,
+        text: String,
+        reply_to: Option<MessageId>,
+    ) -> Self {
+        Self {
+            id,
+            from,
+            name,
+            at,
+            text,
+            reply_to,
+            edited: None,
+            deleted: false,
+            reactions: BTreeMap::new(),
+            mentions: vec![],
+            highlighted: false,
+            seen: BTreeMap::new(),
+            expires_at: None,
+        }
+    }
+
+    /// The text with an "(edited)" or "(deleted)" marker.
+    pub fn display_text(&self) -> String {
+        match (self.deleted, self.edited) {
+            (true, _) => "(deleted)".to_string(),
+            (false, Some(_)) => format!("{:?} (edited)", self.text),
+            (false, None) => format!("{:?}", self.text),
+        }
+    }
+
+    /// The countdown of an ephemeral message, like "expires in 299s".
+    pub fn display_expiry(&self) -> Option<String> {
+        let secs = (self.expires_at? - Local::now()).num_seconds().max(0);
+        Some(format!("expires in {secs}s"))
+    }
+
+    /// Like "👍 x2(bob, carol), ✅ x1(alice)".
+    pub fn display_reactions(&self) -> String {
+        let items: Vec<String> = self
+            .reactions
+            .iter()
+            .map(|(emoji, nodes)| {
+                let names: Vec<&str> = nodes.values().map(|v| v.as_str()).collect();
+                format!("{emoji} x{}({})", nodes.len(), names.join(", "))
+            })
+            .collect();
+        items.join(", ")
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "- id: {}\n  from: {}\n  name: {:?}\n  at: {}",
+            self.id.fmt_short(),
+            self.from,
+            self.name,
+            self.at.to_rfc3339()
+        )?;
+        if let Some(v) = self.reply_to {
+            write!(f, "\n  reply_to: {}", v.fmt_short())?;
+        }
+        write!(f, "\n  text: {}", self.display_text())?;
+        if let Some(v) = self.display_expiry() {
+            write!(f, "\n  ephemeral: {v}")?;
+        }
+        if !self.reactions.is_empty() {
+            write!(f, "\n  reactions: {}", self.display_reactions())?;
+        }
+        Ok(())
+    }
+}
+
+/// A poll in the room, the votes are counted per node id of the signed messages.
+#[derive(Debug, Clone)]
+pub struct Poll {
+    pub id: MessageId,
+    pub from: NodeId,
+    pub name: String,
+    pub at: DateTime<Local>,
+    pub question: String,
+    pub options: Vec<String>,
+    // node => name, the option number and the time of its latest vote
+    pub votes: HashMap<NodeId, (String, usize, DateTime<Local>)>,
+}
+
+impl Poll {
+    pub fn new(
+        id: MessageId,
+        from: NodeId,
+        name: String,
+        at: DateTime<Local>,
+        question: String,
+        options: Vec<String>,
+    ) -> Self {
+        Self { id, from, name, at, question, options, votes: HashMap::new() }
+    }
+
+    /// The names of the voters of each option, in the order of the options.
+    pub fn tally(&self) -> Vec<Vec<String>> {
+        let mut tally = vec![vec![]; self.options.len()];
+        for (name, n, _) in self.votes.values() {
+            if let Some(v) = tally.get_mut(n - 1) {
+                v.push(name.clone());
+            }
+        }
+        tally.iter_mut().for_each(|v| v.sort());
+        tally
+    }
+
+    /// Like "1) rollback\n2) push forward".
+    pub fn display_options(&self) -> String {
+        let items: Vec<String> =
+            self.options.iter().enumerate().map(|(i, v)| format!("{}) {v}", i + 1)).collect();
+        items.join("\n")
+    }
+}
+
+impl fmt::Display for Poll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "id: {}\nfrom: {}\nname: {:?}\nat: {}\nquestion: {:?}\nvotes: {}\noptions:",
+            self.id.fmt_short(),
+            self.from,
+            self.name,
+            self.at.to_rfc3339(),
+            self.question,
+            self.votes.len()
+        )?;
+        for (i, (option, voters)) in self.options.iter().zip(self.tally()).enumerate() {
+            write!(
+                f,
+                "\n- n: {}\n  text: {option:?}\n  votes: {}\n  voters: {voters:?}",
+                i + 1,
+                voters.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoomInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub topic: Option<TopicLine>,
+}
+
+/// Config `run` for the ::run command, the environment is cleared except for `env`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RunConfig {
+    pub enabled: bool,
+    /// Allowed programs, any program is allowed when it's empty.
+    pub allowlist: Vec<String>,
+    pub timeout_secs: u64,
+    /// Max bytes kept of stdout and stderr each, the rest is dropped.
+    pub max_output: usize,
+    pub workdir: Option<String>,
+    /// Environment variables passed to the command.
+    pub env: Vec<String>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allowlist: vec![],
+            timeout_secs: 60,
+            max_output: 64 * 1024,
+            workdir: None,
+            env: ["PATH", "HOME", "USER", "LANG", "TERM"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// Config `net` for the endpoints, the command line options take precedence.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NetConfig {
+    /// Bind address of the gossip endpoint, "0.0.0.0:4433" or just a port, random by default.
+    pub bind: Option<String>,
+    /// Bind address of the blobs endpoint.
+    pub blobs_bind: Option<String>,
+    pub discovery: DiscoveryMode,
+}
+
+/// Config `notify` for the messages that mention us, and the receipts of messages.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Ring the terminal bell on a highlighted message.
+    pub bell: bool,
+    /// Highlight the messages containing any of the keywords, case insensitive.
+    pub keywords: Vec<String>,
+    /// Tell the others which of their messages we displayed, for ::seen.
+    pub receipts: bool,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self { bell: true, keywords: vec![], receipts: true }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TopicTicket {
     pub topic: TopicId,
     pub nodes: Vec<NodeAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 impl TopicTicket {
+    /// The prefix of the compact form, like the kind of iroh's own tickets.
+    pub const KIND: &'static str = "topic";
+
     pub fn from_json(bytes: &[u8]) -> Result<Self> {
         serde_json::from_slice(bytes).map_err(Into::into)
     }
@@ -77,6 +467,22 @@ impl TopicTicket {
         serde_json::to_vec(self).expect("serde_json::to_vec is infallible")
     }
 
+    /// The versioned binary form, postcard of `TopicTicketWire`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let wire = TopicTicketWire::Variant0(Variant0TopicTicket {
+            topic: self.topic,
+            nodes: self.nodes.clone(),
+            name: self.name.clone(),
+            description: self.description.clone(),
+        });
+        postcard::to_stdvec(&wire).expect("postcard::to_stdvec is infallible")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let TopicTicketWire::Variant0(v) = postcard::from_bytes(bytes)?;
+        Ok(Self { topic: v.topic, nodes: v.nodes, name: v.name, description: v.description })
+    }
+
     /*
     pub fn base64_bytes(&self) -> Vec<u8> {
         let bts = serde_json::to_vec(self).expect("serde_json::to_vec is infallible");
@@ -85,17 +491,29 @@ impl TopicTicket {
     */
 
     pub fn base32_bytes(&self) -> Vec<u8> {
-        let bts = serde_json::to_vec(self).expect("serde_json::to_vec is infallible");
-        let mut text = data_encoding::BASE32_NOPAD.encode(&bts);
-        text.make_ascii_lowercase();
-        text.into()
+        self.to_string().into()
     }
 }
 
+// the fields are never skipped, postcard isn't self-describing
+#[derive(Serialize, Deserialize)]
+struct Variant0TopicTicket {
+    topic: TopicId,
+    nodes: Vec<NodeAddr>,
+    name: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum TopicTicketWire {
+    Variant0(Variant0TopicTicket),
+}
+
 impl fmt::Display for TopicTicket {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // let text = general_purpose::STANDARD.encode(&self.to_bytes()[..]);
-        let mut text = data_encoding::BASE32_NOPAD.encode(&self.to_json()[..]);
+        let mut text = Self::KIND.to_string();
+        data_encoding::BASE32_NOPAD.encode_append(&self.to_bytes(), &mut text);
         text.make_ascii_lowercase();
         write!(f, "{}", text)
     }
@@ -104,10 +522,21 @@ impl fmt::Display for TopicTicket {
 impl FromStr for TopicTicket {
     type Err = anyhow::Error;
 
+    /// Accepts the compact form and the legacy base32 of json.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // let bytes = general_purpose::STANDARD.decode(s.as_bytes())?;
-        let bytes = data_encoding::BASE32_NOPAD.decode(s.to_ascii_uppercase().as_bytes())?;
-        Self::from_json(&bytes)
+        match s.strip_prefix(Self::KIND) {
+            Some(v) => {
+                let bytes =
+                    data_encoding::BASE32_NOPAD.decode(v.to_ascii_uppercase().as_bytes())?;
+                Self::from_bytes(&bytes)
+            }
+            None => {
+                let bytes =
+                    data_encoding::BASE32_NOPAD.decode(s.to_ascii_uppercase().as_bytes())?;
+                Self::from_json(&bytes)
+            }
+        }
     }
 }
 
@@ -117,6 +546,20 @@ pub struct MemDB {
     node_id: NodeId,
     name: String,
     pub members: std::sync::Arc<RwLock<HashMap<NodeId, String>>>,
+    // None means the room is open to everyone, a std lock since the router checks it synchronously
+    allowlist: Option<std::sync::Arc<std::sync::RwLock<HashSet<NodeId>>>>,
+    // muted nodes, persisted in profile_dir()/ignored.yaml
+    pub ignored: std::sync::Arc<RwLock<HashMap<NodeId, String>>>,
+    // verified node_id/name bindings, persisted in profile_dir()/verified.yaml
+    pub verified: std::sync::Arc<RwLock<HashMap<NodeId, String>>>,
+    pub room: std::sync::Arc<RwLock<RoomInfo>>,
+    // the gossip neighbors of the room, kept by the subscribe_loop
+    pub neighbors: std::sync::Arc<RwLock<HashSet<NodeId>>>,
+    // the latest MAX_HISTORY messages of the room, oldest first
+    pub history: std::sync::Arc<RwLock<VecDeque<HistoryEntry>>>,
+    // the latest MAX_POLLS polls of the room, oldest first
+    pub polls: std::sync::Arc<RwLock<VecDeque<Poll>>>,
+    pub notify: NotifyConfig,
 }
 
 impl MemDB {
@@ -126,13 +569,319 @@ impl MemDB {
             node_id,
             name,
             members: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            allowlist: None,
+            ignored: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            verified: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            room: std::sync::Arc::new(RwLock::new(RoomInfo::default())),
+            neighbors: std::sync::Arc::new(RwLock::new(HashSet::new())),
+            history: std::sync::Arc::new(RwLock::new(VecDeque::new())),
+            polls: std::sync::Arc::new(RwLock::new(VecDeque::new())),
+            notify: NotifyConfig::default(),
+        }
+    }
+
+    pub fn with_notify(mut self, notify: NotifyConfig) -> Self {
+        let keywords = notify.keywords.iter().map(|v| v.to_lowercase()).collect();
+        self.notify = NotifyConfig { keywords, ..notify };
+        self
+    }
+
+    /// A message is highlighted if it mentions us or contains a keyword.
+    pub fn is_highlighted(&self, text: &str, mentions: &[NodeId]) -> bool {
+        if mentions.contains(&self.node_id) {
+            return true;
+        }
+
+        let text = text.to_lowercase();
+        self.notify.keywords.iter().any(|v| !v.is_empty() && text.contains(v.as_str()))
+    }
+
+    /// Only accept messages and connections from the given node ids (and ourselves).
+    pub fn with_allowlist(mut self, node_ids: Vec<NodeId>) -> Self {
+        let allowlist = HashSet::from_iter(node_ids);
+        self.allowlist = Some(std::sync::Arc::new(std::sync::RwLock::new(allowlist)));
+        self
+    }
+
+    pub fn is_allowed(&self, node_id: &NodeId) -> bool {
+        match &self.allowlist {
+            Some(v) => *node_id == self.node_id || v.read().unwrap().contains(node_id),
+            None => true,
+        }
+    }
+
+    /// Returns false if the node id was already allowed.
+    pub fn allow(&self, node_id: NodeId) -> Result<bool> {
+        match &self.allowlist {
+            Some(v) => Ok(v.write().unwrap().insert(node_id)),
+            None => Err(anyhow!("allowlist mode is disabled")),
+        }
+    }
+
+    /// A copy sharing everything but the members, neighbors, room info, history and polls, which
+    /// are kept per room.
+    pub fn for_room(&self, room: RoomInfo) -> Self {
+        Self {
+            members: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            room: std::sync::Arc::new(RwLock::new(room)),
+            neighbors: std::sync::Arc::new(RwLock::new(HashSet::new())),
+            history: std::sync::Arc::new(RwLock::new(VecDeque::new())),
+            polls: std::sync::Arc::new(RwLock::new(VecDeque::new())),
+            ..self.clone()
+        }
+    }
+
+    /// Returns false if the topic line isn't newer than the current one.
+    pub async fn set_topic(&self, line: TopicLine) -> bool {
+        let mut room = self.room.write().await;
+
+        match &room.topic {
+            Some(v) if (v.timestamp_ms, v.by) >= (line.timestamp_ms, line.by) => false,
+            _ => {
+                room.topic = Some(line);
+                true
+            }
+        }
+    }
+
+    /// Returns false if a message with the same id is already in the history.
+    pub async fn add_history(&self, entry: HistoryEntry) -> bool {
+        let mut history = self.history.write().await;
+        if history.iter().any(|v| v.id == entry.id) {
+            return false;
+        }
+
+        if history.len() >= MAX_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(entry);
+        true
+    }
+
+    /// Remove the expired ephemeral messages, returns them.
+    pub async fn purge_expired(&self) -> Vec<HistoryEntry> {
+        let now = Local::now();
+        let mut history = self.history.write().await;
+        if !history.iter().any(|v| v.expires_at.is_some_and(|v| v <= now)) {
+            return vec![];
+        }
+
+        let (expired, kept): (Vec<_>, Vec<_>) =
+            history.drain(..).partition(|v| v.expires_at.is_some_and(|v| v <= now));
+        *history = kept.into();
+        expired
+    }
+
+    pub async fn get_message(&self, id: &MessageId) -> Option<HistoryEntry> {
+        self.history.read().await.iter().find(|v| v.id == *id).cloned()
+    }
+
+    /// Edit a message, or delete it when text is None, only by its author.
+    /// Returns the entry before the change.
+    pub async fn edit_message(
+        &self,
+        from: &NodeId,
+        id: &MessageId,
+        text: Option<String>,
+        at: DateTime<Local>,
+    ) -> Result<HistoryEntry> {
+        let mut history = self.history.write().await;
+        let entry = history
+            .iter_mut()
+            .find(|v| v.id == *id)
+            .ok_or_else(|| anyhow!("unknown message id: {}", id.fmt_short()))?;
+
+        if entry.from != *from {
+            return Err(anyhow!("not the author of message: {}", id.fmt_short()));
+        }
+        if entry.deleted {
+            return Err(anyhow!("message is deleted: {}", id.fmt_short()));
+        }
+
+        let old = entry.clone();
+        match text {
+            // edits may arrive out of order, keep the latest
+            Some(_) if entry.edited.is_some_and(|v| v >= at) => {
+                return Err(anyhow!("outdated edit of message: {}", id.fmt_short()));
+            }
+            Some(text) => (entry.text, entry.edited) = (text, Some(at)),
+            None => (entry.text, entry.deleted) = (String::new(), true),
+        }
+        Ok(old)
+    }
+
+    /// Returns false if the node already reacted with the emoji.
+    pub async fn add_reaction(
+        &self,
+        id: &MessageId,
+        emoji: String,
+        from: NodeId,
+        name: String,
+    ) -> Result<bool> {
+        let mut history = self.history.write().await;
+        let entry = history
+            .iter_mut()
+            .find(|v| v.id == *id)
+            .ok_or_else(|| anyhow!("unknown message id: {}", id.fmt_short()))?;
+
+        Ok(entry.reactions.entry(emoji).or_default().insert(from, name).is_none())
+    }
+
+    /// Record the receipt of the messages in the history, returns the number recorded.
+    pub async fn add_receipts(
+        &self,
+        ids: &[MessageId],
+        from: NodeId,
+        name: String,
+        at: DateTime<Local>,
+    ) -> usize {
+        let mut history = self.history.write().await;
+        let mut count = 0;
+        for entry in history.iter_mut().filter(|v| ids.contains(&v.id) && v.from != from) {
+            entry.seen.entry(from).or_insert_with(|| (name.clone(), at));
+            count += 1;
+        }
+        count
+    }
+
+    /// Find a message in the history by a prefix of its hex id.
+    pub async fn find_message(&self, key: &str) -> Result<HistoryEntry> {
+        let history = self.history.read().await;
+        find_by_id(history.iter().map(|v| (&v.id, v)), key, "message")
+    }
+
+    /// Returns false if a poll with the same id is already known.
+    pub async fn add_poll(&self, poll: Poll) -> bool {
+        let mut polls = self.polls.write().await;
+        if polls.iter().any(|v| v.id == poll.id) {
+            return false;
+        }
+
+        if polls.len() >= MAX_POLLS {
+            polls.pop_front();
+        }
+        polls.push_back(poll);
+        true
+    }
+
+    /// Find a poll by a prefix of its hex id.
+    pub async fn find_poll(&self, key: &str) -> Result<Poll> {
+        let polls = self.polls.read().await;
+        find_by_id(polls.iter().map(|v| (&v.id, v)), key, "poll")
+    }
+
+    /// Record the vote of a node, replacing its earlier one. Returns the poll after the vote.
+    pub async fn vote(
+        &self,
+        id: &MessageId,
+        n: usize,
+        from: NodeId,
+        name: String,
+        at: DateTime<Local>,
+    ) -> Result<Poll> {
+        let mut polls = self.polls.write().await;
+        let poll = polls
+            .iter_mut()
+            .find(|v| v.id == *id)
+            .ok_or_else(|| anyhow!("unknown poll id: {}", id.fmt_short()))?;
+
+        if !(1..=poll.options.len()).contains(&n) {
+            return Err(anyhow!("option must be 1 to {}, got: {n}", poll.options.len()));
+        }
+        // votes may arrive out of order, keep the latest
+        if poll.votes.get(&from).is_some_and(|v| v.2 >= at) {
+            return Err(anyhow!("outdated vote for poll: {}", id.fmt_short()));
+        }
+
+        poll.votes.insert(from, (name, n, at));
+        Ok(poll.clone())
+    }
+
+    /// The thread of a message: its root in the history and all the replies under it, oldest first.
+    pub async fn thread(&self, id: &MessageId) -> Vec<HistoryEntry> {
+        let history = self.history.read().await;
+        let find = |id: &MessageId| history.iter().find(|v| v.id == *id);
+
+        let mut root = *id;
+        while let Some(v) = find(&root).and_then(|v| v.reply_to).filter(|v| find(v).is_some()) {
+            root = v;
+        }
+
+        let mut ids = HashSet::from([root]);
+        let mut thread = Vec::new();
+        for entry in history.iter() {
+            if entry.id == root || entry.reply_to.is_some_and(|v| ids.contains(&v)) {
+                ids.insert(entry.id);
+                thread.push(entry.clone());
+            }
         }
+        thread
     }
 
     pub fn node(&self) -> (NodeId, String) {
         (self.node_id, self.name.clone())
     }
 
+    /// Find a member by node id or name.
+    pub async fn find_member(&self, key: &str) -> Result<(NodeId, String)> {
+        find_node(&*self.members.read().await, key)
+    }
+
+    pub async fn is_ignored(&self, node_id: &NodeId) -> bool {
+        self.ignored.read().await.contains_key(node_id)
+    }
+
+    /// Load the ignored and verified lists from the data dir.
+    pub async fn load_nodes(&self) -> Result<()> {
+        *self.ignored.write().await = load_nodes(&profile_dir(&self.name)?, IGNORED_FILE).await?;
+        *self.verified.write().await = load_nodes(&profile_dir(&self.name)?, VERIFIED_FILE).await?;
+        Ok(())
+    }
+
+    /// Returns false if the node was already ignored.
+    pub async fn ignore(&self, node_id: NodeId, name: String) -> Result<bool> {
+        let mut ignored = self.ignored.write().await;
+        if ignored.insert(node_id, name).is_some() {
+            return Ok(false);
+        }
+
+        save_nodes(&profile_dir(&self.name)?, IGNORED_FILE, &ignored).await?;
+        Ok(true)
+    }
+
+    /// Remove a node from the ignored list by node id or name.
+    pub async fn unignore(&self, key: &str) -> Result<(NodeId, String)> {
+        let mut ignored = self.ignored.write().await;
+        let (node_id, name) = find_node(&ignored, key)?;
+
+        if ignored.remove(&node_id).is_none() {
+            return Err(anyhow!("not ignored: {node_id}"));
+        }
+
+        save_nodes(&profile_dir(&self.name)?, IGNORED_FILE, &ignored).await?;
+        Ok((node_id, name))
+    }
+
+    /// The safety number shared by us and a peer, both sides get the same one.
+    pub fn fingerprint(&self, node_id: &NodeId) -> String {
+        fingerprint(&self.node_id, node_id)
+    }
+
+    /// Bind the name to the node id, returns the previous name if it was verified.
+    pub async fn mark_verified(&self, node_id: NodeId, name: String) -> Result<Option<String>> {
+        let mut verified = self.verified.write().await;
+        let previous = verified.insert(node_id, name);
+
+        save_nodes(&profile_dir(&self.name)?, VERIFIED_FILE, &verified).await?;
+        Ok(previous)
+    }
+
+    /// Returns the node ids verified with the name, other than the given node id.
+    pub async fn verified_conflicts(&self, node_id: &NodeId, name: &str) -> Vec<NodeId> {
+        let verified = self.verified.read().await;
+        verified.iter().filter(|(k, v)| *k != node_id && v.as_str() == name).map(|v| *v.0).collect()
+    }
+
     pub fn sign_message(&self, message: &Message) -> Bytes {
         let palyload = serde_json::to_vec(message).expect("serde_json::to_vec is infallible");
         let signature = self.secret_key.sign(&palyload);
@@ -152,6 +901,124 @@ impl MemDB {
     }
 }
 
+async fn load_nodes(dir: &Path, filename: &str) -> Result<HashMap<NodeId, String>> {
+    let filepath = dir.join(filename);
+    if !filepath.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let content = fs::read_to_string(&filepath).await?;
+    serde_yaml::from_str(&content).map_err(|e| anyhow!("invalid {}: {e}", filepath.display()))
+}
+
+async fn save_nodes(dir: &Path, filename: &str, nodes: &HashMap<NodeId, String>) -> Result<()> {
+    fs::create_dir_all(dir).await?;
+    fs::write(dir.join(filename), serde_yaml::to_string(nodes)?).await?;
+    Ok(())
+}
+
+/// Hash of both node ids in a fixed order, printed as 8 groups of 5 digits.
+pub fn fingerprint(a: &NodeId, b: &NodeId) -> String {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+
+    let mut buf = Vec::with_capacity(64);
+    buf.extend(a.as_bytes());
+    buf.extend(b.as_bytes());
+    let hash = iroh_blobs::Hash::new(buf);
+
+    let groups: Vec<String> = hash
+        .as_bytes()
+        .chunks(4)
+        .map(|v| u32::from_be_bytes(v.try_into().unwrap()) % 100_000)
+        .map(|v| format!("{v:05}"))
+        .collect();
+
+    format!("{}\n{}", groups[..4].join(" "), groups[4..].join(" "))
+}
+
+/// Find a node by node id or name, an unknown node id is returned with an empty name.
+pub fn find_node(nodes: &HashMap<NodeId, String>, key: &str) -> Result<(NodeId, String)> {
+    if let Ok(node_id) = NodeId::from_str(key) {
+        return Ok((node_id, nodes.get(&node_id).cloned().unwrap_or_default()));
+    }
+
+    let mut matched = nodes.iter().filter(|(_, v)| v.as_str() == key);
+
+    match (matched.next(), matched.next()) {
+        (Some((k, v)), None) => Ok((*k, v.clone())),
+        (Some(_), Some(_)) => Err(anyhow!("ambiguous name {key:?}, use the node_id instead")),
+        (None, _) => Err(anyhow!("unknown name or node_id: {key:?}")),
+    }
+}
+
+/// Find an item by a prefix of its hex id, kind is like "message" for the errors.
+fn find_by_id<'a, T: Clone + 'a>(
+    items: impl Iterator<Item = (&'a MessageId, &'a T)>,
+    key: &str,
+    kind: &str,
+) -> Result<T> {
+    let key = key.to_lowercase();
+    if key.len() < 4 {
+        return Err(anyhow!("{kind} id is too short: {key:?}"));
+    }
+
+    let mut matched = items.filter(|(id, _)| id.to_hex().starts_with(&key));
+    match (matched.next(), matched.next()) {
+        (Some((_, v)), None) => Ok(v.clone()),
+        (Some(_), Some(_)) => Err(anyhow!("ambiguous {kind} id {key:?}, use a longer one")),
+        (None, _) => Err(anyhow!("unknown {kind} id: {key:?}")),
+    }
+}
+
+/// The id of a raw message from `MemDB::sign_message`.
+pub fn message_id(bts: &Bytes) -> MessageId {
+    // the author is part of it, the same payload signed by another node gets another id
+    let (node_id, payload) = (bts.get(..32).unwrap_or_default(), bts.get(96..).unwrap_or_default());
+    iroh_blobs::Hash::new([node_id, payload].concat())
+}
+
+/// The node ids of the members mentioned with @name in the text, a name may be used by several.
+pub fn parse_mentions(text: &str, members: &HashMap<NodeId, String>) -> Vec<NodeId> {
+    let names: HashSet<&str> = text
+        .split_whitespace()
+        .filter_map(|v| v.strip_prefix('@'))
+        .map(|v| v.trim_end_matches(|c: char| ",.:;!?)".contains(c)))
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    let mut node_ids: Vec<NodeId> =
+        members.iter().filter(|(_, v)| names.contains(v.as_str())).map(|v| *v.0).collect();
+    node_ids.sort();
+    node_ids
+}
+
+/// The expiry of an ephemeral message sent at `at`, None if it's out of range.
+pub fn expires_at(at: DateTime<Local>, ttl_secs: u64) -> Option<DateTime<Local>> {
+    at.checked_add_signed(chrono::Duration::seconds(ttl_secs.min(MAX_EPHEMERAL_SECS) as i64))
+}
+
+/// A reaction is a short emoji or word without spaces, like "👍" or "+1".
+pub fn is_valid_reaction(emoji: &str) -> bool {
+    !emoji.is_empty() && emoji.chars().count() <= 16 && !emoji.contains(char::is_whitespace)
+}
+
+/// The first line of a message quoted in a reply.
+pub fn quote_message(parent: Option<&HistoryEntry>, id: &MessageId) -> String {
+    let Some(parent) = parent else {
+        return format!("> {} (not in history)", id.fmt_short());
+    };
+    if parent.deleted {
+        return format!("> {}({:?}): (deleted)", parent.id.fmt_short(), parent.name);
+    }
+
+    let line = parent.text.lines().next().unwrap_or_default();
+    let line = match line.char_indices().nth(60) {
+        Some((i, _)) => format!("{}...", &line[..i]),
+        None => line.to_string(),
+    };
+    format!("> {}({:?}): {line}", parent.id.fmt_short(), parent.name)
+}
+
 pub fn parse_raw_message(bts: &Bytes) -> Result<(NodeId, DateTime<Local>, Message)> {
     if bts.len() <= 96 {
         return Err(anyhow!("invalid raw message length: {}", bts.len()));
@@ -173,7 +1040,9 @@ pub fn parse_raw_message(bts: &Bytes) -> Result<(NodeId, DateTime<Local>, Messag
 
     let at = local_from_millis(message.timestamp_ms)
         .map_err(|e| anyhow!("invalid timestamp_ms: {e:?}"))?;
-    // TODO: check at when it's not an AboutMe
+    if (at - Local::now()).num_seconds() > MAX_CLOCK_SKEW_SECS {
+        return Err(anyhow!("timestamp_ms in the future: {at}"));
+    }
 
     Ok((from, at, message))
 }
@@ -183,3 +1052,281 @@ impl fmt::Display for MemDB {
         write!(f, "node_id={}, name={:?}", self.node_id, self.name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::iroh_secret_key;
+
+    #[test]
+    fn fingerprint_is_symmetric() {
+        let (a, b) = (iroh_secret_key().public(), iroh_secret_key().public());
+
+        assert_eq!(fingerprint(&a, &b), fingerprint(&b, &a));
+        assert_ne!(fingerprint(&a, &b), fingerprint(&a, &a));
+        println!("{}", fingerprint(&a, &b));
+    }
+
+    #[test]
+    fn topic_ticket_encoding() {
+        let node = NodeAddr::new(iroh_secret_key().public())
+            .with_relay_url("https://relay.example.com".parse().unwrap())
+            .with_direct_addresses(["127.0.0.1:4433".parse().unwrap()]);
+        let ticket = TopicTicket {
+            topic: TopicId::from_bytes(rand::random()),
+            nodes: vec![node],
+            name: Some("dev".to_string()),
+            description: None,
+        };
+
+        let text = ticket.to_string();
+        let mut legacy = data_encoding::BASE32_NOPAD.encode(&ticket.to_json());
+        legacy.make_ascii_lowercase();
+        assert!(text.starts_with(TopicTicket::KIND));
+        assert!(text.len() < legacy.len());
+
+        for v in [text, legacy] {
+            let parsed: TopicTicket = v.parse().unwrap();
+            assert_eq!(parsed.topic, ticket.topic);
+            assert_eq!(parsed.nodes, ticket.nodes);
+            assert_eq!(parsed.name, ticket.name);
+            assert_eq!(parsed.description, ticket.description);
+        }
+    }
+
+    // a MemDB of "alice" and her node id
+    fn test_db() -> (MemDB, NodeId) {
+        let secret_key = iroh_secret_key();
+        let node_id = secret_key.public();
+        (MemDB::new(secret_key, node_id, "alice".to_string()), node_id)
+    }
+
+    // a message "hi" from the node with a random id
+    fn test_entry(from: NodeId, reply_to: Option<MessageId>) -> HistoryEntry {
+        let id = MessageId::new(rand::random::<[u8; 32]>());
+        HistoryEntry::new(id, from, "alice".into(), Local::now(), "hi".into(), reply_to)
+    }
+
+    // add a message from ourselves to the history, returns its id
+    async fn add_test_message(mem_db: &MemDB, reply_to: Option<MessageId>) -> MessageId {
+        let entry = test_entry(mem_db.node().0, reply_to);
+        let id = entry.id;
+        mem_db.add_history(entry).await;
+        id
+    }
+
+    #[test]
+    fn allowlist() {
+        let (mem_db, node_id) = test_db();
+        let other = iroh_secret_key().public();
+        assert!(mem_db.is_allowed(&other));
+        assert!(mem_db.allow(other).is_err());
+
+        let mem_db = mem_db.with_allowlist(vec![]);
+        assert!(mem_db.is_allowed(&node_id));
+        assert!(!mem_db.is_allowed(&other));
+        assert!(mem_db.allow(other).unwrap());
+        assert!(!mem_db.allow(other).unwrap());
+        assert!(mem_db.is_allowed(&other));
+    }
+
+    #[tokio::test]
+    async fn set_topic_keeps_latest() {
+        let (mem_db, node_id) = test_db();
+
+        let line = |text: &str, timestamp_ms| TopicLine {
+            text: text.into(),
+            by: node_id,
+            timestamp_ms,
+            signed: Bytes::new(),
+        };
+        assert!(mem_db.set_topic(line("a", 2)).await);
+        assert!(!mem_db.set_topic(line("b", 1)).await);
+        assert!(!mem_db.set_topic(line("a", 2)).await);
+        assert!(mem_db.set_topic(line("c", 3)).await);
+        assert_eq!(mem_db.room.read().await.topic.as_ref().map(|v| v.text.as_str()), Some("c"));
+    }
+
+    #[test]
+    fn topic_from_signed() {
+        let (mem_db, node_id) = test_db();
+
+        let signed = mem_db.sign_msg(Msg::Topic { text: "release on friday".into() });
+        let line = TopicLine::from_signed(signed.clone()).unwrap();
+        assert_eq!((line.text.as_str(), line.by), ("release on friday", node_id));
+
+        let mut forged = signed.to_vec();
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(TopicLine::from_signed(forged.into()).is_err());
+        assert!(TopicLine::from_signed(mem_db.sign_msg(Msg::Typing)).is_err());
+    }
+
+    #[tokio::test]
+    async fn thread_from_history() {
+        let (mem_db, _) = test_db();
+
+        let mut ids = Vec::new();
+        // 0 <- 1 <- 3, 0 <- 4, 2 is unrelated
+        for reply_to in [None, Some(0), None, Some(1), Some(0)] {
+            ids.push(add_test_message(&mem_db, reply_to.map(|v| ids[v])).await);
+        }
+
+        let thread: Vec<_> = mem_db.thread(&ids[3]).await.iter().map(|v| v.id).collect();
+        assert_eq!(thread, vec![ids[0], ids[1], ids[3], ids[4]]);
+        assert_eq!(mem_db.thread(&ids[2]).await.len(), 1);
+
+        let found = mem_db.find_message(&ids[2].to_hex()[..10]).await.unwrap();
+        assert_eq!(found.id, ids[2]);
+        assert!(mem_db.find_message("zz").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn edit_by_author_only() {
+        let (mem_db, node_id) = test_db();
+        let other = iroh_secret_key().public();
+        let (id, at) = (add_test_message(&mem_db, None).await, Local::now());
+
+        let later = |v| at + chrono::Duration::seconds(v);
+        assert!(mem_db.edit_message(&other, &id, Some("x".into()), later(1)).await.is_err());
+        assert!(mem_db.edit_message(&node_id, &id, Some("typo".into()), later(2)).await.is_ok());
+        assert!(mem_db.edit_message(&node_id, &id, Some("old".into()), later(1)).await.is_err());
+        let entry = mem_db.get_message(&id).await.unwrap();
+        assert_eq!(entry.display_text(), "\"typo\" (edited)");
+
+        assert!(mem_db.edit_message(&other, &id, None, later(3)).await.is_err());
+        assert!(mem_db.edit_message(&node_id, &id, None, later(3)).await.is_ok());
+        assert!(mem_db.edit_message(&node_id, &id, Some("x".into()), later(4)).await.is_err());
+        assert_eq!(mem_db.get_message(&id).await.unwrap().display_text(), "(deleted)");
+    }
+
+    #[test]
+    fn mentions_and_keywords() {
+        let (bob, carol) = (iroh_secret_key().public(), iroh_secret_key().public());
+        let members = HashMap::from([(bob, "bob".to_string()), (carol, "carol".to_string())]);
+
+        assert_eq!(parse_mentions("@bob, can you check?", &members), vec![bob]);
+        assert_eq!(parse_mentions("@dave bob@example.com", &members), vec![]);
+        assert_eq!(parse_mentions("@carol @bob @bob", &members).len(), 2);
+
+        let (mem_db, node_id) = test_db();
+        let notify = NotifyConfig { bell: false, keywords: vec!["Prod".into()], receipts: true };
+        let mem_db = mem_db.with_notify(notify);
+        assert!(mem_db.is_highlighted("hi", &[bob, node_id]));
+        assert!(mem_db.is_highlighted("prod is down", &[]));
+        assert!(!mem_db.is_highlighted("staging is down", &[bob]));
+    }
+
+    #[tokio::test]
+    async fn reactions_per_node() {
+        let (mem_db, node_id) = test_db();
+        let other = iroh_secret_key().public();
+        let id = add_test_message(&mem_db, None).await;
+
+        let thumbs_up = "👍".to_string();
+        assert!(mem_db.add_reaction(&id, thumbs_up.clone(), other, "bob".into()).await.unwrap());
+        assert!(!mem_db.add_reaction(&id, thumbs_up.clone(), other, "bob".into()).await.unwrap());
+        assert!(mem_db.add_reaction(&id, thumbs_up, node_id, "alice".into()).await.unwrap());
+        assert!(mem_db.add_reaction(&id, "✅".into(), other, "bob".into()).await.unwrap());
+
+        let reactions = mem_db.get_message(&id).await.unwrap().display_reactions();
+        assert!(reactions.contains("👍 x2(") && reactions.contains("✅ x1(bob)"));
+        assert!(
+            mem_db.add_reaction(&MessageId::EMPTY, "👍".into(), other, "bob".into()).await.is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn receipts_from_others() {
+        let (mem_db, node_id) = test_db();
+        let other = iroh_secret_key().public();
+        let id = add_test_message(&mem_db, None).await;
+
+        let (ids, at) = ([id, MessageId::EMPTY], Local::now());
+        assert_eq!(mem_db.add_receipts(&ids, other, "bob".into(), at).await, 1);
+        assert_eq!(mem_db.add_receipts(&ids, node_id, "alice".into(), at).await, 0);
+        assert!(mem_db.get_message(&id).await.unwrap().seen.contains_key(&other));
+    }
+
+    #[tokio::test]
+    async fn purge_expired_messages() {
+        let (mem_db, node_id) = test_db();
+
+        let mut ids = Vec::new();
+        for ttl_secs in [None, Some(-1), Some(60)] {
+            let mut entry = test_entry(node_id, None);
+            entry.expires_at = ttl_secs.map(|v| Local::now() + chrono::Duration::seconds(v));
+            ids.push(entry.id);
+            mem_db.add_history(entry).await;
+        }
+
+        let expired: Vec<_> = mem_db.purge_expired().await.iter().map(|v| v.id).collect();
+        assert_eq!(expired, vec![ids[1]]);
+        assert!(mem_db.purge_expired().await.is_empty());
+        assert_eq!(mem_db.history.read().await.len(), 2);
+    }
+
+    #[test]
+    fn timestamps_out_of_range() {
+        let (mem_db, _) = test_db();
+        let signed = |timestamp_ms| {
+            let message = Message { nonce: [0; 16], timestamp_ms, msg: Msg::Typing };
+            parse_raw_message(&mem_db.sign_message(&message))
+        };
+        let now = Utc::now().timestamp_millis();
+        assert!(signed(now).is_ok());
+        assert!(signed(now - 365 * 24 * 3600 * 1000).is_ok());
+        assert!(signed(now + 3600 * 1000).is_err());
+        assert!(signed(i64::MAX).is_err());
+
+        let last = local_from_millis(DateTime::<Utc>::MAX_UTC.timestamp_millis()).unwrap();
+        assert!(expires_at(last, 60).is_none());
+        assert!(expires_at(Local::now(), 60).is_some());
+    }
+
+    #[tokio::test]
+    async fn poll_votes_latest_wins() {
+        let (mem_db, node_id) = test_db();
+        let other = iroh_secret_key().public();
+
+        let id = MessageId::new(rand::random::<[u8; 32]>());
+        let options = vec!["rollback".to_string(), "push forward".to_string()];
+        let new_poll = |from, options| {
+            Poll::new(id, from, "alice".into(), Local::now(), "next?".into(), options)
+        };
+        assert!(mem_db.add_poll(new_poll(node_id, options.clone())).await);
+
+        let at = Local::now();
+        let later = at + chrono::Duration::seconds(1);
+        mem_db.vote(&id, 1, node_id, "alice".into(), at).await.unwrap();
+        mem_db.vote(&id, 1, other, "bob".into(), at).await.unwrap();
+        let poll = mem_db.vote(&id, 2, other, "bob".into(), later).await.unwrap();
+        assert_eq!(poll.tally(), vec![vec!["alice".to_string()], vec!["bob".to_string()]]);
+
+        // an outdated or invalid vote is dropped
+        assert!(mem_db.vote(&id, 1, other, "bob".into(), at).await.is_err());
+        assert!(mem_db.vote(&id, 3, node_id, "alice".into(), later).await.is_err());
+        assert!(mem_db.vote(&MessageId::EMPTY, 1, other, "bob".into(), later).await.is_err());
+
+        // a poll with a known id doesn't reset the votes
+        assert!(!mem_db.add_poll(new_poll(other, options)).await);
+        let found = mem_db.find_poll(&id.to_hex()[..8]).await.unwrap();
+        assert_eq!(found.votes.len(), 2);
+        assert!(mem_db.find_poll("zz").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn message_ids_per_author() {
+        let ((mem_db, node_id), (other, _)) = (test_db(), test_db());
+
+        // the same payload signed by another node
+        let message = Message::new(Msg::Resync);
+        let id = message_id(&mem_db.sign_message(&message));
+        assert_eq!(id, message_id(&mem_db.sign_message(&message)));
+        assert_ne!(id, message_id(&other.sign_message(&message)));
+
+        let entry = HistoryEntry { id, ..test_entry(node_id, None) };
+        assert!(mem_db.add_history(entry.clone()).await);
+        assert!(!mem_db.add_history(entry).await);
+        assert_eq!(mem_db.history.read().await.len(), 1);
+    }
+}
@@ -1,23 +1,57 @@
-use crate::structs::{EOF_BLOCK, MemDB, Message, Msg};
-use crate::utils::content_to_file;
+use crate::structs::{
+    EOF_BLOCK, HistoryEntry, MAX_POLL_OPTIONS, MemDB, Message, Msg, Poll, TYPING_INTERVAL,
+    TopicLine, expires_at, is_valid_reaction, message_id, quote_message,
+};
+use crate::utils::{content_to_file, highlight, ring_bell};
 
 use crate::structs::parse_raw_message;
 
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
 use anyhow::Result;
+use chrono::Local;
 use futures_lite::StreamExt;
-use iroh::PublicKey;
+use iroh::{NodeId, PublicKey};
 use iroh_gossip::net::{self, Event, GossipEvent, GossipReceiver, GossipSender};
+use tokio::time::{self, Instant};
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info, warn}; // Level, instrument
+use tracing::{debug, error, info, warn}; // Level, instrument
+
+// retry joining with backoff while there are no neighbors
+const REJOIN_MIN: Duration = Duration::from_secs(5);
+const REJOIN_MAX: Duration = Duration::from_secs(60);
+// at most one resync request in the interval
+const RESYNC_INTERVAL: Duration = Duration::from_secs(10);
+// receipts are batched, an id is 52 bytes in json
+const RECEIPT_DELAY: Duration = Duration::from_secs(2);
+const MAX_RECEIPT_IDS: usize = 50;
 
+/// `bootstrap` are the nodes of the ticket, they and every node seen are used to rejoin
+/// when all the neighbors are lost.
 pub async fn subscribe_loop(
     cancel_token: CancellationToken,
     mem_db: MemDB,
     sender: GossipSender,
     mut receiver: GossipReceiver,
+    bootstrap: Vec<NodeId>,
 ) -> Result<()> {
     let (_node_id, name) = mem_db.node();
-    let about_me = Message::new(Msg::AboutMe { name: name.clone() }); // fixed .nonce and .at
+    let mut about_me = Message::new(Msg::AboutMe { name: name.clone(), signed_topic: None }); // fixed .nonce and .at
+
+    let mut known: HashSet<NodeId> = bootstrap.into_iter().collect();
+    let mut disconnected = false;
+    let mut backoff = REJOIN_MIN;
+    let mut rejoin_at = Instant::now() + backoff;
+    let mut resync_at: Option<Instant> = None;
+    let mut receipts = Vec::new();
+    let mut receipt_at: Option<Instant> = None;
+    // the last typing notification shown of each node
+    let mut typing: HashMap<NodeId, Instant> = HashMap::new();
+    // purge the expired ephemeral messages
+    let mut purge = time::interval(Duration::from_secs(1));
 
     let get_entry = async |from: &PublicKey| {
         // if it's a `Message` message, get the name from the map and print the message
@@ -49,30 +83,102 @@ pub async fn subscribe_loop(
                 warn!("<-- subscribe_loop received cancellation.");
                 break;
             }
+            _ = time::sleep_until(rejoin_at) => {
+                if mem_db.neighbors.read().await.is_empty() && !known.is_empty() {
+                    backoff = (backoff * 2).min(REJOIN_MAX);
+                    if disconnected {
+                        info!("=== Rejoining: {} known node(s), next retry in {backoff:?}", known.len());
+                    }
+                    if let Err(e) = sender.join_peers(known.iter().cloned().collect()).await {
+                        error!("join peers error: {e:?}");
+                    }
+                }
+                rejoin_at = Instant::now() + backoff;
+                continue;
+            }
+            _ = time::sleep_until(receipt_at.unwrap_or_else(Instant::now)), if receipt_at.is_some() => {
+                receipt_at = None;
+                for ids in receipts.chunks(MAX_RECEIPT_IDS) {
+                    let msg = Msg::Receipt { ids: ids.to_vec() };
+                    if let Err(e) = sender.broadcast(mem_db.sign_msg(msg)).await {
+                        error!("Receipt broadcast error: {e:?}");
+                    }
+                }
+                receipts.clear();
+                continue;
+            }
+            _ = purge.tick() => {
+                for entry in mem_db.purge_expired().await {
+                    info!("=== Expired: {}({:?}), id={}", entry.from.fmt_short(), entry.name, entry.id.fmt_short());
+                }
+                continue;
+            }
             v = receiver.try_next() => v?,
         };
 
         let event = match event {
             Some(Event::Lagged) => {
-                warn!("=== Lagged");
+                // messages were dropped, ask the others to announce themselves again
+                if resync_at.is_none_or(|v| v.elapsed() > RESYNC_INTERVAL) {
+                    warn!("=== Lagged: some messages are lost, requesting a resync");
+                    resync_at = Some(Instant::now());
+                    if let Err(e) = sender.broadcast(mem_db.sign_msg(Msg::Resync)).await {
+                        error!("Resync broadcast error: {e:?}");
+                    }
+                }
                 continue;
             }
             Some(Event::Gossip(v)) => v,
             None => continue,
         };
 
+        // rebuild AboutMe only when the room topic changes, so gossip can still deduplicate it
+        let signed_topic = mem_db.room.read().await.topic.as_ref().map(|v| v.signed.to_vec());
+        if !matches!(&about_me.msg, Msg::AboutMe { signed_topic: v, .. } if *v == signed_topic) {
+            about_me = Message::new(Msg::AboutMe { name: name.clone(), signed_topic });
+        }
+
         let message: net::Message = match event {
             GossipEvent::Joined(node_ids) => {
                 info!("=== Joined: {:?}", node_ids);
+                known.extend(node_ids.iter().cloned());
+                mem_db.neighbors.write().await.extend(node_ids);
+                if let Err(e) = sender.broadcast(mem_db.sign_message(&about_me)).await {
+                    error!("AboutMe broadcast error: {e:?}");
+                }
                 continue;
             }
             GossipEvent::NeighborUp(from) => {
                 info!("=== NeighborUp: {from}");
+                known.insert(from);
+                mem_db.neighbors.write().await.insert(from);
+
+                if disconnected {
+                    // the members were removed with the neighbors, ask them to announce again
+                    info!("=== Reconnected: {from}");
+                    (disconnected, backoff) = (false, REJOIN_MIN);
+                    if let Err(e) = sender.broadcast(mem_db.sign_msg(Msg::Resync)).await {
+                        error!("Resync broadcast error: {e:?}");
+                    }
+                }
                 continue;
             }
             GossipEvent::NeighborDown(from) => {
+                let mut neighbors = mem_db.neighbors.write().await;
+                neighbors.remove(&from);
                 let entry = remove_entry(&from).await;
                 info!("=== NeighborDown: {entry}");
+
+                if neighbors.is_empty() && known.is_empty() {
+                    warn!("=== Disconnected: no neighbors left, waiting for nodes to join us");
+                } else if neighbors.is_empty() {
+                    warn!(
+                        "=== Disconnected: no neighbors left, rejoining with {} known node(s)",
+                        known.len()
+                    );
+                    (disconnected, backoff) = (true, REJOIN_MIN);
+                    rejoin_at = Instant::now() + backoff;
+                }
                 continue;
             }
             GossipEvent::Received(v) => v,
@@ -81,6 +187,7 @@ pub async fn subscribe_loop(
         // let from = msg.delivered_from;
         // dbg!(&from);
         // let (from, msg, at) = match Message::from_bytes(&message.content[64..]) {
+        let id = message_id(&message.content);
         let (from, at, msg) = match parse_raw_message(&message.content) {
             Ok(v) => (v.0, v.1, v.2.msg),
             Err(e) => {
@@ -92,19 +199,103 @@ pub async fn subscribe_loop(
             }
         };
 
+        if !mem_db.is_allowed(&from) {
+            debug!("drop message from a node not in the allowlist: {from}");
+            continue;
+        }
+
+        if matches!(
+            msg,
+            Msg::Message { .. }
+                | Msg::SendFile { .. }
+                | Msg::ShareFile { .. }
+                | Msg::CommandOutput { .. }
+                | Msg::Edit { .. }
+                | Msg::Delete { .. }
+                | Msg::Reaction { .. }
+                | Msg::Typing
+        ) && mem_db.is_ignored(&from).await
+        {
+            debug!("drop message from an ignored node: {from}");
+            continue;
+        }
+
         // deserialize the message and match on the message type:
         match msg {
+            Msg::Resync => {
+                // a fresh AboutMe, the fixed one may be deduplicated by gossip
+                let msg = Msg::AboutMe {
+                    name: name.clone(),
+                    signed_topic: mem_db
+                        .room
+                        .read()
+                        .await
+                        .topic
+                        .as_ref()
+                        .map(|v| v.signed.to_vec()),
+                };
+                if let Err(e) = sender.broadcast(mem_db.sign_msg(msg)).await {
+                    error!("AboutMe broadcast error: {e:?}");
+                }
+                continue;
+            }
             Msg::Bye => {
+                // left on purpose, don't rejoin with it
+                known.remove(&from);
                 let entry = remove_entry(&from).await;
                 warn!("<-- Bye: {entry}, {at}");
             }
-            Msg::AboutMe { name: ref peer_name } => {
-                let mut members = mem_db.members.write().await;
-                // if it's an `AboutMe` message add and entry into the map and print the name
-                if !members.contains_key(&from) {
-                    members.insert(from, peer_name.clone());
+            Msg::AboutMe { name: ref peer_name, signed_topic } => {
+                known.insert(from);
+                let (is_new, same_name) = {
+                    let mut members = mem_db.members.write().await;
+                    let same_name: Vec<_> = members
+                        .iter()
+                        .filter(|(k, v)| **k != from && *v == peer_name)
+                        .map(|v| *v.0)
+                        .collect();
+
+                    // if it's an `AboutMe` message add and entry into the map and print the name
+                    let is_new = match members.entry(from) {
+                        std::collections::hash_map::Entry::Vacant(e) => {
+                            e.insert(peer_name.clone());
+                            true
+                        }
+                        std::collections::hash_map::Entry::Occupied(_) => false,
+                    };
+                    (is_new, same_name)
+                };
+
+                // the members lock is released, the checks below take other locks
+                if is_new {
                     // println!("<-- Peer: {} is now known as {:?}", from, name);
                     info!("<-- NewPeer: {from}\nname={peer_name:?}, at={at}");
+
+                    if !same_name.is_empty() {
+                        warn!("=== NewPeer {from} uses the same name as: {same_name:?}");
+                    }
+
+                    let conflicts = mem_db.verified_conflicts(&from, peer_name).await;
+                    if !conflicts.is_empty() {
+                        error!(
+                            "!!! WARNING: {} claims the name {:?} which is verified for {:?}\n{}",
+                            from, peer_name, conflicts, "!!! It may be an impersonation.",
+                        );
+                    }
+                }
+
+                match signed_topic.map(|v| TopicLine::from_signed(v.into())) {
+                    Some(Ok(line)) if mem_db.is_allowed(&line.by) => {
+                        let text = line.text.clone();
+                        if mem_db.set_topic(line).await {
+                            info!("=== Topic: {text}");
+                        }
+                    }
+                    Some(Ok(line)) => {
+                        debug!("drop a topic by a node not in the allowlist: {}", line.by)
+                    }
+                    Some(Err(e)) => warn!("drop an invalid topic relayed by {from}: {e}"),
+                    None => {}
                 }
 
                 // println!("??? send about_me");
@@ -112,9 +303,158 @@ pub async fn subscribe_loop(
                     error!("AboutMe broadcast error: {e:?}");
                 }
             }
-            Msg::Message { text } => {
+            Msg::Typing => {
+                if typing.get(&from).is_none_or(|v| v.elapsed() > TYPING_INTERVAL * 2) {
+                    let name = mem_db.members.read().await.get(&from).cloned();
+                    let name = name.unwrap_or_else(|| from.fmt_short());
+                    info!("=== {name} is typing...");
+                }
+                typing.insert(from, Instant::now());
+                continue;
+            }
+            Msg::Message { text, reply_to, mentions, ttl_secs } => {
+                typing.remove(&from);
+                let expires_at = match ttl_secs.map(|v| expires_at(at, v)) {
+                    Some(None) => {
+                        debug!("drop an ephemeral message with an invalid expiry from: {from}");
+                        continue;
+                    }
+                    v => v.flatten(),
+                };
+                if expires_at.is_some_and(|v| v <= Local::now()) {
+                    debug!("drop an expired ephemeral message from: {from}");
+                    continue;
+                }
+
+                if mem_db.get_message(&id).await.is_some() {
+                    debug!("drop a message with a known id from: {from}");
+                    continue;
+                }
+
+                let mut header = format!("{}, id={}", get_entry(&from).await, id.fmt_short());
+                if let Some(v) = expires_at {
+                    header.push_str(&format!(", expires in {}s", (v - Local::now()).num_seconds()));
+                }
+                let quote = match &reply_to {
+                    Some(v) => {
+                        format!("{}\n", quote_message(mem_db.get_message(v).await.as_ref(), v))
+                    }
+                    None => String::new(),
+                };
+
+                let highlighted = mem_db.is_highlighted(&text, &mentions);
+                if highlighted {
+                    let text = highlight(text.trim_end());
+                    info!("<<< Mention: {header}\n{quote}{text}");
+                    if mem_db.notify.bell {
+                        ring_bell();
+                    }
+                } else {
+                    info!("<<< Message: {header}\n{quote}{}", text.trim_end());
+                }
+
+                let name = mem_db.members.read().await.get(&from).cloned().unwrap_or_default();
+                let mut entry = HistoryEntry::new(id, from, name, at, text, reply_to);
+                (entry.mentions, entry.highlighted, entry.expires_at) =
+                    (mentions, highlighted, expires_at);
+                mem_db.add_history(entry).await;
+
+                if mem_db.notify.receipts {
+                    receipts.push(id);
+                    receipt_at.get_or_insert_with(|| Instant::now() + RECEIPT_DELAY);
+                }
+            }
+            Msg::Topic { text } => {
+                let line = TopicLine {
+                    text: text.clone(),
+                    by: from,
+                    timestamp_ms: at.timestamp_millis(),
+                    signed: message.content.clone(),
+                };
+                if !mem_db.set_topic(line).await {
+                    debug!("drop an outdated topic from: {from}");
+                    continue;
+                }
+
                 let entry = get_entry(&from).await;
-                info!("<<< Message: {entry}\n{}", text.trim_end());
+                info!("<-- Topic: {entry}\n{text}");
+            }
+            Msg::Edit { id, text } => {
+                let entry = get_entry(&from).await;
+                if let Err(e) = mem_db.edit_message(&from, &id, Some(text.clone()), at).await {
+                    warn!("drop an edit from {entry}: {e}");
+                    continue;
+                }
+                info!("<<< Edit: {entry}, id={} (edited)\n{}", id.fmt_short(), text.trim_end());
+            }
+            Msg::Delete { id } => {
+                let entry = get_entry(&from).await;
+                if let Err(e) = mem_db.edit_message(&from, &id, None, at).await {
+                    warn!("drop a delete from {entry}: {e}");
+                    continue;
+                }
+                info!("<<< Delete: {entry}, id={} (deleted)", id.fmt_short());
+            }
+            Msg::Reaction { id, emoji } => {
+                let entry = get_entry(&from).await;
+                if !is_valid_reaction(&emoji) {
+                    warn!("drop an invalid reaction from {entry}: {emoji:?}");
+                    continue;
+                }
+
+                let name = mem_db.members.read().await.get(&from).cloned().unwrap_or_default();
+                match mem_db.add_reaction(&id, emoji.clone(), from, name).await {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => debug!("reaction from {entry}: {e}"),
+                }
+
+                let quote = quote_message(mem_db.get_message(&id).await.as_ref(), &id);
+                info!("<<< Reaction: {entry}, {emoji}\n{quote}");
+            }
+            Msg::Poll { question, options } => {
+                let entry = get_entry(&from).await;
+                if !(2..=MAX_POLL_OPTIONS).contains(&options.len()) {
+                    warn!("drop a poll with {} options from {entry}", options.len());
+                    continue;
+                }
+
+                let name = mem_db.members.read().await.get(&from).cloned().unwrap_or_default();
+                let poll = Poll::new(id, from, name, at, question, options);
+                let text = format!("{}\n{}", poll.question, poll.display_options());
+                if !mem_db.add_poll(poll).await {
+                    debug!("drop a poll with a known id from: {from}");
+                    continue;
+                }
+
+                // kept for the votes of the others, only hidden from an ignored node
+                if mem_db.is_ignored(&from).await {
+                    continue;
+                }
+                info!("<<< Poll: {entry}, id={}\n{text}", id.fmt_short());
+            }
+            Msg::Vote { poll, n } => {
+                let entry = get_entry(&from).await;
+                let name = mem_db.members.read().await.get(&from).cloned().unwrap_or_default();
+                match mem_db.vote(&poll, n, from, name, at).await {
+                    // counted in the results, only hidden from an ignored node
+                    Ok(_) if mem_db.is_ignored(&from).await => continue,
+                    Ok(v) => info!(
+                        "<<< Vote: {entry}, poll={}, {n}) {}",
+                        poll.fmt_short(),
+                        v.options[n - 1]
+                    ),
+                    Err(e) => {
+                        warn!("drop a vote from {entry}: {e}");
+                        continue;
+                    }
+                }
+            }
+            Msg::Receipt { ids } => {
+                let name = mem_db.members.read().await.get(&from).cloned().unwrap_or_default();
+                let count = mem_db.add_receipts(&ids, from, name, at).await;
+                debug!("receipt from {from}: {count} of {} message(s) in history", ids.len());
+                continue;
             }
             Msg::SendFile { filename, content } => {
                 let entry = get_entry(&from).await;
@@ -136,6 +476,43 @@ pub async fn subscribe_loop(
                 let entry = get_entry(&from).await;
                 info!("<-- Got ShareFile: {entry}, size={size}\n{ticket} {filename}");
             }
+            Msg::CommandOutput {
+                args,
+                success,
+                status,
+                elapsed_ms,
+                stdout,
+                stderr,
+                truncated,
+                ticket,
+            } => {
+                let entry = get_entry(&from).await;
+                let truncated = if truncated { " (truncated)" } else { "" };
+
+                let output = match ticket {
+                    Some(v) => format!("--- output is too large, receive it with:\n{v} output.txt"),
+                    None => format!(
+                        "--- stdout:\n{}\n--- stderr:\n{}",
+                        stdout.trim_end(),
+                        stderr.trim_end()
+                    ),
+                };
+
+                let text = format!(
+                    "$ {}\n=== {}, elapsed={}ms{}\n{}",
+                    shell_words::join(&args),
+                    status,
+                    elapsed_ms,
+                    truncated,
+                    output,
+                );
+
+                if success {
+                    info!("<-- CommandOutput: {entry}\n{text}");
+                } else {
+                    warn!("<-- CommandOutput: {entry}\n{text}");
+                }
+            }
         }
 
         println!("{}", EOF_BLOCK);
@@ -35,6 +35,17 @@ pub async fn share_file(
     Ok((blob.size, ticket))
 }
 
+pub async fn share_bytes(
+    blobs_client: &MemClient,
+    node_id: NodeId,
+    bytes: Vec<u8>,
+) -> Result<(u64, BlobTicket)> {
+    let blob = blobs_client.add_bytes(bytes).await?;
+    let ticket = BlobTicket::new(node_id.into(), blob.hash, blob.format)?;
+
+    Ok((blob.size, ticket))
+}
+
 pub async fn receive_file(
     blobs_client: &MemClient,
     ticket: BlobTicket,
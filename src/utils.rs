@@ -1,9 +1,15 @@
-use std::path;
+use std::{
+    io::{IsTerminal, Write},
+    net::SocketAddr,
+    path,
+    time::Duration,
+};
 
 use crate::structs::MAX_FILESIZE;
 
 use anyhow::{Result, anyhow};
 use iroh::SecretKey;
+use qrcode::{QrCode, render::unicode::Dense1x2};
 //use rand::RngCore;
 use chrono::{DateTime, Local, SecondsFormat, Utc};
 use rand::prelude::*;
@@ -68,12 +74,12 @@ pub fn load_yaml(filepath: &str) -> Result<Value> {
 }
 
 pub fn config_get<'a>(value: &'a Value, sub: &str) -> Option<&'a Value> {
-    sub.split('.').fold(Some(value), |acc, key| acc?.get(key))
+    sub.split('.').try_fold(value, |acc, key| acc.get(key))
 }
 
 pub fn local_now() -> String {
     let now = Local::now();
-    return now.to_rfc3339_opts(SecondsFormat::Millis, true);
+    now.to_rfc3339_opts(SecondsFormat::Millis, true)
 }
 
 pub fn local_from_millis(millis: i64) -> Result<DateTime<Local>> {
@@ -103,6 +109,49 @@ pub fn iroh_secret_key() -> SecretKey {
     SecretKey::from_bytes(&buf)
 }
 
+/// Render the text as a QR code with unicode half blocks, light on dark for terminals.
+pub fn qr_code(text: &str) -> Result<String> {
+    let code = QrCode::new(text.as_bytes())?;
+
+    Ok(code.render::<Dense1x2>().dark_color(Dense1x2::Light).light_color(Dense1x2::Dark).build())
+}
+
+/// Parse a bind address, a bare port means all the ipv4 interfaces.
+pub fn parse_bind_addr(text: &str) -> Result<SocketAddr> {
+    match text.parse::<u16>() {
+        Ok(port) => Ok(SocketAddr::from(([0, 0, 0, 0], port))),
+        Err(_) => text.parse().map_err(|e| anyhow!("invalid bind address {text:?}: {e}")),
+    }
+}
+
+/// Parse a duration like "90", "30s", "5m", "2h" or "1d", seconds by default.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let (num, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+    let num: u64 = num.parse().map_err(|_| anyhow!("invalid duration: {text:?}"))?;
+
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(anyhow!("invalid duration unit: {text:?}, expected s, m, h or d")),
+    };
+    Ok(Duration::from_secs(num.saturating_mul(secs)))
+}
+
+/// Bold yellow on a terminal, as is otherwise.
+pub fn highlight(text: &str) -> String {
+    match std::io::stdout().is_terminal() {
+        true => format!("\x1b[1;33m{text}\x1b[0m"),
+        false => text.to_string(),
+    }
+}
+
+pub fn ring_bell() {
+    print!("\x07");
+    let _ = std::io::stdout().flush();
+}
+
 pub fn split_first_space(mut s: &str, trim: bool) -> (&str, Option<&str>) {
     if trim {
         s = s.trim();
@@ -186,6 +235,24 @@ pub async fn read_file_content(filename: &str, max_size: u64) -> Result<Vec<u8>>
     fs::read(&filepath).await.map_err(|e| anyhow!("failed to read file, {e:?}"))
 }
 
+/// The directory for received files and local state: ~/apps/data/iroh-chat-cli
+pub fn data_dir() -> Result<path::PathBuf> {
+    let home_dir = std::env::home_dir().ok_or(anyhow!("can't get home dir"))?;
+
+    // path::Path::new("data").join("received_files")
+    Ok(path::Path::new(&home_dir).join("apps").join("data").join("iroh-chat-cli"))
+}
+
+/// The directory for the local state kept across restarts, the node id may change on every run
+/// but the nickname doesn't: ~/apps/data/iroh-chat-cli/profiles/<name>
+pub fn profile_dir(name: &str) -> Result<path::PathBuf> {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "-_".contains(c) { c } else { '_' })
+        .collect();
+    Ok(data_dir()?.join("profiles").join(name))
+}
+
 pub async fn content_to_file(content: Vec<u8>, filename: &str) -> Result<String> {
     if content.len() > MAX_FILESIZE.try_into().unwrap() {
         return Err(anyhow!("file size is too large than {MAX_FILESIZE}"));
@@ -197,14 +264,8 @@ pub async fn content_to_file(content: Vec<u8>, filename: &str) -> Result<String>
         None => return Err(anyhow!("invalid filepath")),
     };
 
-    let home_dir = std::env::home_dir().ok_or(anyhow!("can't get home dir"))?;
-
     // let prefix = Local::now().format("%Y-%m-%d-%s").to_string();
-    let dir = path::Path::new(&home_dir) // path::Path::new("data").join("received_files")
-        .join("apps")
-        .join("data")
-        .join("iroh-chat-cli")
-        .join(Utc::now().format("%Y-%m-%d-utc").to_string());
+    let dir = data_dir()?.join(Utc::now().format("%Y-%m-%d-utc").to_string());
 
     let filepath = dir.join(filename);
 
@@ -217,12 +278,21 @@ pub async fn content_to_file(content: Vec<u8>, filename: &str) -> Result<String>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn load_config() {
-        let yaml = load_yaml("configs/local.yaml").unwrap();
-        let secret_key = config_get(&yaml, "iroh.secret_key").and_then(|v| v.as_str()).unwrap();
-        dbg!(&secret_key);
+        // a config of its own, configs/local.yaml is local to each developer
+        let secret_key = iroh_secret_key();
+        let filepath =
+            std::env::temp_dir().join(format!("iroh-chat-cli-{}.yaml", std::process::id()));
+        std::fs::write(&filepath, format!("iroh:\n  secret_key: {secret_key}\n")).unwrap();
+
+        let yaml = load_yaml(filepath.to_str().unwrap());
+        std::fs::remove_file(&filepath).unwrap();
+        let yaml = yaml.unwrap();
+        let val = config_get(&yaml, "iroh.secret_key").and_then(|v| v.as_str()).unwrap();
+        assert_eq!(SecretKey::from_str(val).unwrap().public(), secret_key.public());
     }
 
     #[test]
@@ -238,6 +308,16 @@ mod tests {
         println!("--> signature: {signature}");
     }
 
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+
     #[test]
     fn time() {
         let at = Utc::now().timestamp_millis();
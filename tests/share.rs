@@ -11,6 +11,7 @@ use tracing::{error, info}; // Level, instrument, warn
 use tracing_subscriber::EnvFilter;
 
 #[tokio::test]
+#[ignore = "interactive: cargo test --test share -- --ignored share <filepath>"]
 async fn main() -> Result<()> {
     // Grab all passed in arguments, the first one is the binary itself, so we skip it.
     let args: Vec<String> = std::env::args().skip(1).collect();
@@ -56,7 +57,7 @@ async fn main() -> Result<()> {
             }
 
             tokio::signal::ctrl_c().await?;
-            println!("");
+            println!();
             // Gracefully shut down the node
             router.shutdown().await?;
         }
@@ -46,7 +46,7 @@ async fn main() -> io::Result<()> {
 
             // Ctrl+C support
             _ = tokio::signal::ctrl_c() => {
-                println!("");
+                println!();
                 warn!("received Ctrl+C, exiting.");
                 break;
             }
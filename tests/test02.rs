@@ -8,6 +8,7 @@ use crossterm::{
 };
 
 #[test]
+#[ignore = "interactive: requires a terminal"]
 fn main() -> std::io::Result<()> {
     enable_raw_mode()?;
 
@@ -27,13 +28,13 @@ fn main() -> std::io::Result<()> {
 
         match key_event.code {
             KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
                 execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
                 writeln!(stdout, "Ctrl+C pressed, exiting.")?;
                 break;
             }
             KeyCode::Esc => {
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
                 execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
                 writeln!(stdout, "ESC pressed, exiting.")?;
                 break;
@@ -46,7 +47,7 @@ fn main() -> std::io::Result<()> {
                 write!(stdout, "{}", c)?;
             }
             KeyCode::Enter => {
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
                 execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
                 write!(stdout, "> ")?;
             }
@@ -9,6 +9,7 @@ use crossterm::{
 };
 
 #[test]
+#[ignore = "interactive: requires a terminal"]
 fn main() -> std::io::Result<()> {
     enable_raw_mode()?;
 
@@ -28,14 +29,14 @@ fn main() -> std::io::Result<()> {
 
         match key_event.code {
             KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
                 execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
                 writeln!(stdout, "Ctrl+C pressed, exiting.")?;
                 break;
             }
             KeyCode::Char('s') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
                 // ?? can't use Ctrl+Enter
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
                 execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
                 writeln!(stdout, "Ctrl+S pressed, sending.")?;
                 break;
@@ -45,28 +46,20 @@ fn main() -> std::io::Result<()> {
                 cursor_pos += 1;
             }
             KeyCode::Left => {
-                if cursor_pos > 0 {
-                    cursor_pos -= 1;
-                }
+                cursor_pos = cursor_pos.saturating_sub(1);
             }
-            KeyCode::Right => {
-                if cursor_pos < buffer.len() {
-                    cursor_pos += 1;
-                }
+            KeyCode::Right if cursor_pos < buffer.len() => {
+                cursor_pos += 1;
             }
-            KeyCode::Backspace => {
-                if cursor_pos > 0 {
-                    buffer.remove(cursor_pos - 1);
-                    cursor_pos -= 1;
-                }
+            KeyCode::Backspace if cursor_pos > 0 => {
+                buffer.remove(cursor_pos - 1);
+                cursor_pos -= 1;
             }
-            KeyCode::Delete => {
-                if cursor_pos < buffer.len() {
-                    buffer.remove(cursor_pos);
-                }
+            KeyCode::Delete if cursor_pos < buffer.len() => {
+                buffer.remove(cursor_pos);
             }
             KeyCode::Enter => {
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
                 buffer.clear();
                 cursor_pos = 0;
             }
@@ -8,6 +8,7 @@ use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::test]
+#[ignore = "interactive: runs until SIGINT/SIGTERM"]
 async fn main() {
     utils::log2stdout(EnvFilter::new("info"));
 
@@ -43,7 +44,7 @@ async fn main() {
             (fuse1.await, ans2)
         }
         _ = signal::ctrl_c() => {
-            println!("");
+            println!();
             error!("<-- received Ctrl+C.");
             warn!("--> cancel token");
             cancel_token.cancel();
@@ -51,11 +52,15 @@ async fn main() {
             (fuse1.await, fuse2.await)
         }
         _ = sigint.recv() => {
-            println!("");
+            println!();
             error!("<-- received SIGINT (Ctrl+C)");
+            warn!("--> cancel token");
+            cancel_token.cancel();
+            info!("<-- waitting for both to fully shutdown.");
+            (fuse1.await, fuse2.await)
         }
         _ = sigterm.recv() => {
-            println!("");
+            println!();
             error!("<-- received SIGTERM (kill)");
             warn!("--> cancel token");
             cancel_token.cancel();